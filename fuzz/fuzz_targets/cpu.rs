@@ -0,0 +1,59 @@
+//! Runs arbitrary programs under arbitrary quirks. Any panic is a bug: a bad
+//! program must only ever stop the machine with a `Chip8Error`.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use chip8::{Chip8, IndexIncrement, Input, Quirks};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Debug, Arbitrary)]
+struct Run {
+    flags: [bool; 12],
+    load_store_increment: u8,
+    seed: u64,
+    keys: Vec<u16>,
+    program: Vec<u8>,
+}
+
+fuzz_target!(|run: Run| {
+    let flags = run.flags;
+    let quirks = Quirks {
+        shift_in_place: flags[0],
+        load_store_increment: match run.load_store_increment % 3 {
+            0 => IndexIncrement::Unchanged,
+            1 => IndexIncrement::X,
+            _ => IndexIncrement::XPlusOne,
+        },
+        jump_uses_vx: flags[1],
+        logic_resets_vf: flags[2],
+        clip_sprites: flags[3],
+        add_to_i_sets_vf: flags[4],
+        display_wait: flags[5],
+        extended_memory: flags[6],
+        vip_hires: flags[7],
+        vip_like_random: flags[8],
+        key_wait_on_press: flags[9],
+        super_chip_instructions: flags[10],
+        xo_chip_instructions: flags[11],
+    };
+    let mut chip8 = match Chip8::new(&run.program, quirks) {
+        Ok(chip8) => chip8,
+        Err(_) => return,
+    };
+    chip8.set_seed(run.seed);
+    let keys = if run.keys.is_empty() {
+        vec![0]
+    } else {
+        run.keys
+    };
+    for frame in 0..256 {
+        chip8.set_input(Input::from_bits(keys[frame % keys.len()]));
+        for _ in 0..32 {
+            if chip8.cycle().is_err() {
+                return;
+            }
+        }
+        chip8.tick();
+    }
+});
@@ -0,0 +1,23 @@
+//! Feeds arbitrary bytes to everything that decodes them: the instruction
+//! decoder, the disassembler, save states and movies.
+
+#![no_main]
+
+use chip8::disassembler::{self, Syntax};
+use chip8::movie::Movie;
+use chip8::{Chip8, Instruction, Quirks};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    for word in data.chunks_exact(2) {
+        let opcode = u16::from_be_bytes([word[0], word[1]]);
+        if let Some(instruction) = Instruction::from_opcode(opcode) {
+            assert_eq!(instruction.to_opcode(), opcode);
+        }
+    }
+    disassembler::disassemble(data, Syntax::Octo);
+    disassembler::disassemble(data, Syntax::Classic);
+    let mut chip8 = Chip8::new(&[0x12, 0x00], Quirks::default()).unwrap();
+    let _ = chip8.load_state(data);
+    let _ = Movie::from_bytes(data);
+});
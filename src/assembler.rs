@@ -0,0 +1,473 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cpu::{Instruction, PROGRAM_START};
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+const RESERVED_NAMES: [&str; 10] = ["I", "[I]", "DT", "ST", "K", "F", "HF", "B", "R", "LONG"];
+const MNEMONICS: [&str; 32] = [
+    "SYS", "SCD", "SCU", "CLS", "RET", "SCR", "SCL", "EXIT", "LOW", "HIGH", "JP", "CALL", "SE",
+    "SNE", "SAVE", "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN", "SHL", "RND",
+    "DRW", "SKP", "SKNP", "PLANE", "AUDIO", "PITCH",
+];
+
+/// An error in assembly source, with the line it is on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssemblerError {
+    pub path: PathBuf,
+    /// 1-based.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.path.display(), self.line, self.message)
+    }
+}
+
+impl Error for AssemblerError {}
+
+/// Assembles a program to be loaded at 0x200.
+///
+/// The source uses the mnemonics of the classic disassembly listing, such as
+/// `LD V0, 0x05`, `DRW V0, V1, 5` and `LD I, LONG 0x1234`, one instruction
+/// per line. Besides instructions, a line can hold:
+///
+/// - a label, `name:`, optionally followed by an instruction;
+/// - `:byte` followed by values separated by commas, or by whitespace if
+///   there are no commas;
+/// - `:const NAME value`, which can only refer to labels and constants
+///   defined above it;
+/// - `:include "file"`, which assembles another file in place, relative to
+///   the directory of the including one.
+///
+/// Values are decimal, `0x` hex or `0b` binary numbers, labels and constants,
+/// combined with `+` and `-`. Comments start with `;`. `path` is used to
+/// resolve includes and in error messages.
+pub fn assemble(source: &str, path: &Path) -> Result<Vec<u8>, AssemblerError> {
+    let mut assembler = Assembler {
+        symbols: HashMap::new(),
+        statements: Vec::new(),
+        address: PROGRAM_START,
+    };
+    assembler.read(source, path, 0)?;
+    let mut program = Vec::new();
+    for statement in &assembler.statements {
+        let bytes = assembler
+            .encode(&statement.kind)
+            .map_err(|message| statement.error(message))?;
+        program.extend(bytes);
+    }
+    Ok(program)
+}
+
+struct Statement {
+    path: PathBuf,
+    line: usize,
+    kind: StatementKind,
+}
+
+impl Statement {
+    fn error(&self, message: String) -> AssemblerError {
+        AssemblerError {
+            path: self.path.clone(),
+            line: self.line,
+            message,
+        }
+    }
+}
+
+/// A statement whose operands are only evaluated once every label is known.
+enum StatementKind {
+    Instruction {
+        mnemonic: String,
+        operands: Vec<String>,
+    },
+    Bytes(Vec<String>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    V(u8),
+    I,
+    /// `[I]`, the memory I points to.
+    IndirectI,
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    BigFont,
+    Bcd,
+    Flags,
+    Long(i64),
+    Value(i64),
+}
+
+struct Assembler {
+    symbols: HashMap<String, i64>,
+    statements: Vec<Statement>,
+    /// The address of the next statement.
+    address: usize,
+}
+
+impl Assembler {
+    /// Splits the source into statements and assigns addresses to labels.
+    fn read(&mut self, source: &str, path: &Path, depth: usize) -> Result<(), AssemblerError> {
+        for (i, line) in source.lines().enumerate() {
+            let error = |message| AssemblerError {
+                path: path.to_path_buf(),
+                line: i + 1,
+                message,
+            };
+            let mut line = line.split(';').next().unwrap().trim();
+
+            if let Some(end) = line.find(':').filter(|&end| end > 0) {
+                let name = &line[..end];
+                if is_identifier(name) {
+                    self.define(name, self.address as i64).map_err(error)?;
+                    line = line[end + 1..].trim();
+                }
+            }
+            if line.is_empty() {
+                continue;
+            }
+
+            let (word, rest) = match line.find(char::is_whitespace) {
+                Some(end) => (&line[..end], line[end..].trim()),
+                None => (line, ""),
+            };
+            let kind = match word {
+                ":byte" => {
+                    let values = split_values(rest);
+                    if values.is_empty() {
+                        return Err(error("expected values after `:byte`".to_string()));
+                    }
+                    StatementKind::Bytes(values)
+                }
+                ":const" => {
+                    let (name, value) = match rest.find(char::is_whitespace) {
+                        Some(end) => (&rest[..end], rest[end..].trim()),
+                        None => return Err(error("expected `:const NAME value`".to_string())),
+                    };
+                    let value = self.evaluate(value).map_err(error)?;
+                    self.define(name, value).map_err(error)?;
+                    continue;
+                }
+                ":include" => {
+                    let file = rest
+                        .strip_prefix('"')
+                        .and_then(|rest| rest.strip_suffix('"'))
+                        .ok_or_else(|| error("expected `:include \"file\"`".to_string()))?;
+                    if depth == MAX_INCLUDE_DEPTH {
+                        return Err(error("includes are nested too deeply".to_string()));
+                    }
+                    let include_path = path.parent().unwrap_or_else(|| Path::new("")).join(file);
+                    let source = fs::read_to_string(&include_path).map_err(|e| {
+                        error(format!("failed to read {}: {}", include_path.display(), e))
+                    })?;
+                    self.read(&source, &include_path, depth + 1)?;
+                    continue;
+                }
+                _ if word.starts_with(':') => {
+                    return Err(error(format!("unknown directive `{}`", word)));
+                }
+                _ => {
+                    let mnemonic = word.to_ascii_uppercase();
+                    let operands: Vec<String> = if rest.is_empty() {
+                        Vec::new()
+                    } else {
+                        rest.split(',').map(|s| s.trim().to_string()).collect()
+                    };
+                    if operands.iter().any(String::is_empty) {
+                        return Err(error("empty operand".to_string()));
+                    }
+                    StatementKind::Instruction { mnemonic, operands }
+                }
+            };
+            self.address += statement_len(&kind);
+            self.statements.push(Statement {
+                path: path.to_path_buf(),
+                line: i + 1,
+                kind,
+            });
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str, value: i64) -> Result<(), String> {
+        if !is_identifier(name) {
+            return Err(format!("`{}` is not a valid name", name));
+        }
+        let upper = name.to_ascii_uppercase();
+        if RESERVED_NAMES.contains(&upper.as_str()) || register(&upper).is_some() {
+            return Err(format!("`{}` is a reserved name", name));
+        }
+        if self.symbols.insert(name.to_string(), value).is_some() {
+            return Err(format!("`{}` is already defined", name));
+        }
+        Ok(())
+    }
+
+    fn evaluate(&self, expression: &str) -> Result<i64, String> {
+        let mut total: i64 = 0;
+        let mut sign = 1;
+        let mut rest = expression.trim();
+        if rest.is_empty() {
+            return Err("expected a value".to_string());
+        }
+        loop {
+            if let Some(after) = rest.strip_prefix('-') {
+                sign = -sign;
+                rest = after.trim_start();
+                continue;
+            }
+            let end = rest.find(['+', '-']).unwrap_or(rest.len());
+            let term = rest[..end].trim();
+            let value = match term.chars().next() {
+                Some(c) if c.is_ascii_digit() => parse_number(term)?,
+                Some(_) => *self
+                    .symbols
+                    .get(term)
+                    .ok_or_else(|| format!("unknown name `{}`", term))?,
+                None => return Err(format!("malformed value `{}`", expression)),
+            };
+            total = value
+                .checked_mul(sign)
+                .and_then(|value| total.checked_add(value))
+                .ok_or_else(|| format!("`{}` is out of range", expression))?;
+            rest = &rest[end..];
+            match rest.chars().next() {
+                Some('+') => sign = 1,
+                Some('-') => sign = -1,
+                _ => return Ok(total),
+            }
+            rest = rest[1..].trim_start();
+        }
+    }
+
+    fn operand(&self, operand: &str) -> Result<Operand, String> {
+        let upper = operand.to_ascii_uppercase();
+        let operand = match upper.as_str() {
+            "I" => Operand::I,
+            "[I]" => Operand::IndirectI,
+            "DT" => Operand::DelayTimer,
+            "ST" => Operand::SoundTimer,
+            "K" => Operand::Key,
+            "F" => Operand::Font,
+            "HF" => Operand::BigFont,
+            "B" => Operand::Bcd,
+            "R" => Operand::Flags,
+            _ => match register(&upper) {
+                Some(x) => Operand::V(x),
+                None => match upper.strip_prefix("LONG ") {
+                    Some(_) => Operand::Long(self.evaluate(&operand[5..])?),
+                    None => Operand::Value(self.evaluate(operand)?),
+                },
+            },
+        };
+        Ok(operand)
+    }
+
+    fn encode(&self, kind: &StatementKind) -> Result<Vec<u8>, String> {
+        let (mnemonic, operands) = match kind {
+            StatementKind::Bytes(values) => {
+                return values
+                    .iter()
+                    .map(|value| self.evaluate(value).and_then(byte))
+                    .collect();
+            }
+            StatementKind::Instruction { mnemonic, operands } => (mnemonic, operands),
+        };
+        let operands = operands
+            .iter()
+            .map(|operand| self.operand(operand))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        use Instruction::*;
+        use Operand::*;
+        let instruction = match (mnemonic.as_str(), operands.as_slice()) {
+            ("SYS", &[Value(a)]) => RCA1802 {
+                address: address(a)?,
+            },
+            ("SCD", &[Value(n)]) => ScrollDown { rows: nibble(n)? },
+            ("SCU", &[Value(n)]) => ScrollUp { rows: nibble(n)? },
+            ("CLS", &[]) => ClearScreen,
+            ("RET", &[]) => Return,
+            ("SCR", &[]) => ScrollRight,
+            ("SCL", &[]) => ScrollLeft,
+            ("EXIT", &[]) => Exit,
+            ("LOW", &[]) => LowRes,
+            ("HIGH", &[]) => HighRes,
+            ("JP", &[Value(a)]) => Jump {
+                address: address(a)?,
+            },
+            ("JP", &[V(0), Value(a)]) => JumpOffset {
+                address: address(a)?,
+            },
+            ("CALL", &[Value(a)]) => Subroutine {
+                address: address(a)?,
+            },
+            ("SE", &[V(x), Value(n)]) => IfEqualConst {
+                register: x,
+                value: byte(n)?,
+            },
+            ("SE", &[V(a), V(b)]) => IfEqualRegister { a, b },
+            ("SNE", &[V(x), Value(n)]) => IfNotEqualConst {
+                register: x,
+                value: byte(n)?,
+            },
+            ("SNE", &[V(a), V(b)]) => IfNotEqualRegister { a, b },
+            ("SAVE", &[V(a), V(b)]) => SaveRange { a, b },
+            ("LOAD", &[V(a), V(b)]) => LoadRange { a, b },
+            ("LD", &[V(x), Value(n)]) => SetConst {
+                register: x,
+                value: byte(n)?,
+            },
+            ("LD", &[V(dest), V(src)]) => SetRegister { dest, src },
+            ("LD", &[I, Value(a)]) => SetI {
+                address: address(a)?,
+            },
+            ("LD", &[I, Long(a)]) => {
+                let target = long(a)?;
+                let mut bytes = SetILong.to_opcode().to_be_bytes().to_vec();
+                bytes.extend_from_slice(&target.to_be_bytes());
+                return Ok(bytes);
+            }
+            ("LD", &[V(register), DelayTimer]) => GetTimer { register },
+            ("LD", &[V(register), Key]) => AwaitInput { register },
+            ("LD", &[DelayTimer, V(register)]) => SetTimer { register },
+            ("LD", &[SoundTimer, V(register)]) => SetSound { register },
+            ("LD", &[Font, V(register)]) => SetIToFontChar { register },
+            ("LD", &[BigFont, V(register)]) => SetIToBigFontChar { register },
+            ("LD", &[Bcd, V(register)]) => BinaryCodedDecimal { register },
+            ("LD", &[IndirectI, V(register)]) => RegisterDump { register },
+            ("LD", &[V(register), IndirectI]) => RegisterLoad { register },
+            ("LD", &[Flags, V(register)]) => SaveFlags { register },
+            ("LD", &[V(register), Flags]) => LoadFlags { register },
+            ("ADD", &[V(x), Value(n)]) => AddConst {
+                register: x,
+                value: byte(n)?,
+            },
+            ("ADD", &[V(a), V(b)]) => Add { a, b },
+            ("ADD", &[I, V(register)]) => AddToI { register },
+            ("OR", &[V(a), V(b)]) => Or { a, b },
+            ("AND", &[V(a), V(b)]) => And { a, b },
+            ("XOR", &[V(a), V(b)]) => Xor { a, b },
+            ("SUB", &[V(a), V(b)]) => Sub { a, b },
+            ("SUBN", &[V(a), V(b)]) => NegSub { a, b },
+            ("SHR", &[V(a), V(b)]) => ShiftRight { a, b },
+            ("SHR", &[V(a)]) => ShiftRight { a, b: a },
+            ("SHL", &[V(a), V(b)]) => ShiftLeft { a, b },
+            ("SHL", &[V(a)]) => ShiftLeft { a, b: a },
+            ("RND", &[V(x), Value(n)]) => Rand {
+                register: x,
+                value: byte(n)?,
+            },
+            ("DRW", &[V(x), V(y), Value(n)]) => DrawSprite {
+                x,
+                y,
+                height: nibble(n)?,
+            },
+            ("SKP", &[V(register)]) => IfPressed { register },
+            ("SKNP", &[V(register)]) => IfNotPressed { register },
+            ("PLANE", &[Value(n)]) if (0..=3).contains(&n) => SelectPlanes { planes: n as u8 },
+            ("PLANE", &[Value(n)]) => return Err(format!("plane {} is not between 0 and 3", n)),
+            ("AUDIO", &[]) => LoadAudio,
+            ("PITCH", &[V(register)]) => SetPitch { register },
+            _ if MNEMONICS.contains(&mnemonic.as_str()) => {
+                return Err(format!("invalid operands for `{}`", mnemonic));
+            }
+            _ => return Err(format!("unknown instruction `{}`", mnemonic)),
+        };
+        Ok(instruction.to_opcode().to_be_bytes().to_vec())
+    }
+}
+
+fn statement_len(kind: &StatementKind) -> usize {
+    match kind {
+        StatementKind::Bytes(values) => values.len(),
+        StatementKind::Instruction { mnemonic, operands } => {
+            let long = mnemonic == "LD"
+                && operands
+                    .get(1)
+                    .is_some_and(|operand| operand.to_ascii_uppercase().starts_with("LONG "));
+            if long {
+                4
+            } else {
+                2
+            }
+        }
+    }
+}
+
+fn split_values(s: &str) -> Vec<String> {
+    let values: Vec<&str> = if s.contains(',') {
+        s.split(',').collect()
+    } else {
+        s.split_whitespace().collect()
+    };
+    values
+        .iter()
+        .map(|value| value.trim().to_string())
+        .collect()
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Parses `V0` to `VF`, given in upper case.
+fn register(s: &str) -> Option<u8> {
+    s.strip_prefix('V')
+        .filter(|x| x.len() == 1)
+        .and_then(|x| u8::from_str_radix(x, 16).ok())
+}
+
+fn parse_number(s: &str) -> Result<i64, String> {
+    let lower = s.to_ascii_lowercase();
+    let result = if let Some(digits) = lower.strip_prefix("0x") {
+        i64::from_str_radix(digits, 16)
+    } else if let Some(digits) = lower.strip_prefix("0b") {
+        i64::from_str_radix(digits, 2)
+    } else {
+        lower.parse()
+    };
+    result.map_err(|_| format!("malformed number `{}`", s))
+}
+
+fn address(value: i64) -> Result<u16, String> {
+    if !(0..=0xFFF).contains(&value) {
+        return Err(format!("address {} does not fit in 12 bits", value));
+    }
+    Ok(value as u16)
+}
+
+fn long(value: i64) -> Result<u16, String> {
+    if !(0..=0xFFFF).contains(&value) {
+        return Err(format!("address {} does not fit in 16 bits", value));
+    }
+    Ok(value as u16)
+}
+
+/// Negative values down to -128 are stored as two's complement.
+fn byte(value: i64) -> Result<u8, String> {
+    if !(-0x80..=0xFF).contains(&value) {
+        return Err(format!("{} does not fit in a byte", value));
+    }
+    Ok(value as u8)
+}
+
+fn nibble(value: i64) -> Result<u8, String> {
+    if !(0..=0xF).contains(&value) {
+        return Err(format!("{} is not between 0 and 15", value));
+    }
+    Ok(value as u8)
+}
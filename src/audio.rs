@@ -1,16 +1,29 @@
-use rodio::Sink;
+use std::f32::consts::PI;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rodio::{Sink, Source};
+
+const OUTPUT_SAMPLE_RATE: u32 = 44100;
+const BEEP_FREQUENCY: f32 = 1024.0;
+const VOLUME: f32 = 0.25;
 
 pub struct Audio {
     sink: Sink,
+    pattern: Arc<Mutex<Option<Pattern>>>,
 }
 
 impl Audio {
     pub fn new() -> Self {
-        let audio_source = rodio::source::SineWave::new(1024);
+        let pattern = Arc::new(Mutex::new(None));
+        let audio_source = Buzzer {
+            pattern: pattern.clone(),
+            phase: 0.0,
+        };
         let sink = Sink::new(&rodio::default_output_device().unwrap());
         sink.pause();
         sink.append(audio_source);
-        Self { sink }
+        Self { sink, pattern }
     }
 
     pub fn play(&self) {
@@ -20,4 +33,68 @@ impl Audio {
     pub fn pause(&self) {
         self.sink.pause()
     }
+
+    /// Plays an XO-CHIP audio pattern at the given sample rate instead of the
+    /// default beep, or goes back to the beep if `bits` is `None`.
+    pub fn set_pattern(&self, bits: Option<&[u8; 16]>, sample_rate: f32) {
+        let pattern = bits.map(|&bits| Pattern { bits, sample_rate });
+        *self.pattern.lock().unwrap() = pattern;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Pattern {
+    bits: [u8; 16],
+    sample_rate: f32,
+}
+
+/// An endless source playing either a sine beep or the current audio pattern.
+struct Buzzer {
+    pattern: Arc<Mutex<Option<Pattern>>>,
+    /// Position within the current waveform, in cycles of the beep or in
+    /// samples of the pattern.
+    phase: f32,
+}
+
+impl Iterator for Buzzer {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let pattern = *self.pattern.lock().unwrap();
+        let sample = match pattern {
+            Some(Pattern { bits, sample_rate }) => {
+                let i = self.phase as usize % 128;
+                let bit = bits[i / 8] >> (7 - i % 8) & 1;
+                self.phase = (self.phase + sample_rate / OUTPUT_SAMPLE_RATE as f32) % 128.0;
+                if bit == 1 {
+                    VOLUME
+                } else {
+                    -VOLUME
+                }
+            }
+            None => {
+                self.phase = (self.phase + BEEP_FREQUENCY / OUTPUT_SAMPLE_RATE as f32) % 1.0;
+                VOLUME * (2.0 * PI * self.phase).sin()
+            }
+        };
+        Some(sample)
+    }
+}
+
+impl Source for Buzzer {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        OUTPUT_SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
 }
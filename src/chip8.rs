@@ -1,42 +1,179 @@
-use crate::audio::Audio;
 use crate::cpu::CPU;
 use crate::display::Display;
+use crate::error::Chip8Error;
 use crate::input::Input;
+use crate::quirks::Quirks;
+use crate::state::{self, SaveStateError, StateReader, StateWriter};
+use crate::trace::Tracer;
 
 pub struct Chip8 {
     cpu: CPU,
     display: Display,
-    audio: Audio,
     input: Input,
+    rom_hash: u64,
+    tracer: Option<Tracer>,
 }
 
 impl Chip8 {
-    pub fn new(program: &[u8]) -> Self {
-        Self {
-            cpu: CPU::new(program),
+    pub fn new(program: &[u8], quirks: Quirks) -> Result<Self, Chip8Error> {
+        Ok(Self {
+            cpu: CPU::new(program, quirks)?,
             display: Display::new(),
-            audio: Audio::new(),
             input: Input::new(),
+            rom_hash: state::rom_hash(program),
+            tracer: None,
+        })
+    }
+
+    /// See [`CPU::set_seed`].
+    pub fn set_seed(&mut self, seed: u64) {
+        self.cpu.set_seed(seed)
+    }
+
+    /// The hash save states use to check that they belong to this ROM.
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    /// Serializes the complete machine: memory, registers, timers, stack,
+    /// quirks, display and pressed keys.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.bytes(state::MAGIC);
+        writer.u16(state::VERSION);
+        writer.u64(self.rom_hash);
+        self.cpu.write_state(&mut writer);
+        self.display.write_state(&mut writer);
+        writer.u16(self.input.bits());
+        writer.into_inner()
+    }
+
+    /// Restores a state from `save_state`. The machine is left untouched if
+    /// the state cannot be loaded.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let mut reader = StateReader::new(data);
+        if reader.bytes(state::MAGIC.len()) != Ok(&state::MAGIC[..]) {
+            return Err(SaveStateError::NotASaveState);
+        }
+        let version = reader.u16()?;
+        if version != state::VERSION {
+            return Err(SaveStateError::UnsupportedVersion { version });
         }
+        let found = reader.u64()?;
+        if found != self.rom_hash {
+            return Err(SaveStateError::RomMismatch {
+                expected: self.rom_hash,
+                found,
+            });
+        }
+        let mut cpu = self.cpu.clone();
+        cpu.read_state(&mut reader)?;
+        let mut display = self.display.clone();
+        display.read_state(&mut reader)?;
+        let input = Input::from_bits(reader.u16()?);
+        if !reader.is_empty() {
+            return Err(SaveStateError::Corrupt);
+        }
+        self.cpu = cpu;
+        self.display = display;
+        self.input = input;
+        Ok(())
     }
 
-    pub fn cycle(&mut self) {
-        self.cpu.cycle(&mut self.display, &self.input);
+    /// Executes one instruction. Key events that came in since the last
+    /// cycle are seen by this instruction only.
+    pub fn cycle(&mut self) -> Result<(), Chip8Error> {
+        let result = match &mut self.tracer {
+            Some(tracer) => tracer.cycle(&mut self.cpu, &mut self.display, &self.input),
+            None => self.cpu.cycle(&mut self.display, &self.input),
+        };
+        self.input.clear_events();
+        result
     }
 
     pub fn tick(&mut self) {
         self.cpu.tick();
-        if self.cpu.should_play_sound() {
-            self.audio.play();
-        } else {
-            self.audio.pause();
-        }
+    }
+
+    pub fn should_play_sound(&self) -> bool {
+        self.cpu.should_play_sound()
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.cpu.is_halted()
+    }
+
+    pub fn error(&self) -> Option<&Chip8Error> {
+        self.cpu.error()
+    }
+
+    pub fn has_exited(&self) -> bool {
+        self.cpu.has_exited()
+    }
+
+    pub fn rpl_flags(&self) -> &[u8; 16] {
+        self.cpu.rpl_flags()
+    }
+
+    pub fn set_rpl_flags(&mut self, flags: [u8; 16]) {
+        self.cpu.set_rpl_flags(flags)
+    }
+
+    pub fn audio_pattern(&self) -> Option<&[u8; 16]> {
+        self.cpu.audio_pattern()
+    }
+
+    pub fn audio_sample_rate(&self) -> f32 {
+        self.cpu.audio_sample_rate()
+    }
+
+    /// See [`CPU::trace_memory`].
+    pub fn trace_memory(&mut self, enabled: bool) {
+        self.cpu.trace_memory(enabled)
+    }
+
+    /// Traces every instruction run from now on, or stops tracing.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    pub fn tracer(&self) -> Option<&Tracer> {
+        self.tracer.as_ref()
+    }
+
+    /// Stops tracing and returns the tracer, to `finish` it.
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub(crate) fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
     }
 
     pub fn display(&self) -> &Display {
         &self.display
     }
 
+    pub fn input(&self) -> &Input {
+        &self.input
+    }
+
+    /// Holds the keys `input` holds, pressing and releasing the keys that
+    /// change.
+    pub fn set_input(&mut self, input: Input) {
+        for key in 0..16 {
+            if input.is_key_pressed(key) {
+                self.input.key_pressed(key);
+            } else {
+                self.input.key_released(key);
+            }
+        }
+    }
+
     pub fn key_pressed(&mut self, key: u8) {
         self.input.key_pressed(key)
     }
@@ -0,0 +1,370 @@
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use chip8::disassembler::Syntax;
+use chip8::octo::Platform;
+use chip8::{IndexIncrement, Quirks};
+use structopt::StructOpt;
+
+use crate::renderer::Palette;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "chip8", about = "A CHIP-8 interpreter")]
+pub enum Command {
+    /// Run a ROM
+    ///
+    /// F5 saves the machine to the current save state slot and F9 loads it
+    /// back. F6 and F7 select the previous and next of the ten slots, which
+    /// are kept next to the ROM with `state0` to `state9` extensions.
+    /// Holding Backspace runs the game backwards. F11 switches between
+    /// fullscreen and a window.
+    Run(RunOptions),
+    /// Print a ROM as assembly
+    Disasm(DisasmOptions),
+    /// Assemble a program into a ROM
+    Asm(AsmOptions),
+    /// Run a ROM without a window or audio and print the final display and
+    /// its hash
+    Headless(HeadlessOptions),
+    /// Play a recorded movie without a window and print the final display
+    Replay(ReplayOptions),
+    /// Print the hash that identifies a ROM in the config file
+    Hash(HashOptions),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct RunOptions {
+    /// Path to the ROM to run, or to an Octo program with an `8o`
+    /// extension to compile first
+    #[structopt(parse(from_os_str))]
+    pub rom: PathBuf,
+
+    /// Number of instructions executed per second
+    #[structopt(short, long, default_value = "720")]
+    pub cycles_per_second: u32,
+
+    /// Rate at which the delay and sound timers count down, in Hz
+    #[structopt(short, long, default_value = "60")]
+    pub tick_rate: u32,
+
+    /// How far back holding Backspace can rewind, in seconds
+    #[structopt(long, default_value = "10")]
+    pub rewind_seconds: u32,
+
+    /// Open a window instead of going fullscreen
+    #[structopt(short, long)]
+    pub windowed: bool,
+
+    /// Size of a CHIP-8 pixel in windowed mode, in screen pixels. Resizing
+    /// the window scales the display by the largest whole number that fits
+    #[structopt(short, long, default_value = "10")]
+    pub scale: u32,
+
+    /// Colours of the four XO-CHIP pixel values as comma-separated RRGGBB hex:
+    /// off, plane 1, plane 2 and both planes
+    #[structopt(long, default_value = "000000,ffffff,aaaaaa,555555")]
+    pub palette: Palette,
+
+    #[structopt(flatten)]
+    pub machine: MachineOptions,
+
+    /// Config file with the key and gamepad button mapping [default:
+    /// `chip8/config.toml` in the user's config directory, if it exists]
+    #[structopt(long, parse(from_os_str))]
+    pub config: Option<PathBuf>,
+
+    /// File the SUPER-CHIP RPL user flags are kept in [default: the ROM path
+    /// with an `rpl` extension]
+    #[structopt(long, parse(from_os_str))]
+    pub rpl_flags: Option<PathBuf>,
+
+    /// Seed for the random numbers of CXNN, to make a run repeatable
+    /// [default: a random seed]
+    #[structopt(long)]
+    pub seed: Option<u64>,
+
+    #[structopt(flatten)]
+    pub trace: TraceOptions,
+
+    /// Record the keypad input of the run into a movie file, written on
+    /// exit. Rewinding and loading states are not available while recording
+    #[structopt(long, parse(from_os_str), conflicts_with = "debug")]
+    pub record: Option<PathBuf>,
+
+    /// Start paused and read debugger commands from the terminal
+    #[structopt(short, long)]
+    pub debug: bool,
+
+    /// Wait for a GDB client to connect to this port on localhost before
+    /// starting, and let it control the machine
+    #[structopt(long, conflicts_with_all = &["debug", "record"])]
+    pub gdb: Option<u16>,
+}
+
+impl RunOptions {
+    pub fn rpl_flags_path(&self) -> PathBuf {
+        self.rpl_flags
+            .clone()
+            .unwrap_or_else(|| self.rom.with_extension("rpl"))
+    }
+}
+
+/// How the machine interprets programs, shared by the subcommands that run
+/// one.
+#[derive(Debug, StructOpt)]
+pub struct MachineOptions {
+    /// Quirk profile: default, vip, chip48, schip or xochip
+    #[structopt(short, long, default_value = "default", parse(try_from_str = parse_profile))]
+    pub quirks: Quirks,
+
+    /// Override one quirk of the profile as NAME=VALUE. Names are shift_in_place,
+    /// load_store_increment (none, x or x+1), jump_uses_vx, logic_resets_vf,
+    /// clip_sprites, add_to_i_sets_vf, display_wait, extended_memory,
+    /// vip_hires, vip_like_random, key_wait_on_press, super_chip_instructions
+    /// and xo_chip_instructions
+    #[structopt(long = "quirk", number_of_values = 1)]
+    pub quirk_overrides: Vec<QuirkOverride>,
+}
+
+impl MachineOptions {
+    /// The quirk profile with all overrides applied.
+    pub fn quirks(&self) -> Quirks {
+        let mut quirks = self.quirks;
+        for quirk in &self.quirk_overrides {
+            quirk.apply(&mut quirks);
+        }
+        quirks
+    }
+
+    /// The instruction set Octo programs are compiled for: XO-CHIP with
+    /// extended memory, plain CHIP-8 for the VIP and CHIP-48 profiles and
+    /// SUPER-CHIP otherwise.
+    pub fn platform(&self) -> Platform {
+        if self.quirks().extended_memory {
+            Platform::XoChip
+        } else if self.quirks == Quirks::COSMAC_VIP || self.quirks == Quirks::CHIP_48 {
+            Platform::Chip8
+        } else {
+            Platform::SuperChip
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct HeadlessOptions {
+    /// Path to the ROM to run, or to an Octo program with an `8o`
+    /// extension to compile first
+    #[structopt(parse(from_os_str))]
+    pub rom: PathBuf,
+
+    /// Maximum number of frames to run. The run stops early when the
+    /// program halts or reaches an instruction that jumps to itself
+    #[structopt(short, long, default_value = "600")]
+    pub frames: u32,
+
+    /// Number of instructions executed per frame
+    #[structopt(long, default_value = "12")]
+    pub cycles_per_frame: u32,
+
+    #[structopt(flatten)]
+    pub machine: MachineOptions,
+
+    /// Seed for the random numbers of CXNN
+    #[structopt(long, default_value = "0")]
+    pub seed: u64,
+
+    /// Write the final display to a PNG file
+    #[structopt(long, parse(from_os_str))]
+    pub png: Option<PathBuf>,
+
+    /// Colours of the four pixel values in the PNG, as for `run`
+    #[structopt(long, default_value = "000000,ffffff,aaaaaa,555555")]
+    pub palette: Palette,
+
+    /// Only print the hash of the final display, not the display itself
+    #[structopt(short = "H", long)]
+    pub hash_only: bool,
+
+    #[structopt(flatten)]
+    pub trace: TraceOptions,
+}
+
+/// Instruction tracing, shared by `run` and `headless`.
+#[derive(Debug, StructOpt)]
+pub struct TraceOptions {
+    /// Log every executed instruction to a file. If the machine halts with
+    /// an error, the instructions that led up to it are written at the end
+    #[structopt(long, parse(from_os_str))]
+    pub trace: Option<PathBuf>,
+
+    /// Only log instructions at addresses in this range, given in hex as
+    /// START-END. The instructions written on an error are not filtered
+    #[structopt(long, requires = "trace", parse(try_from_str = parse_range))]
+    pub trace_range: Option<RangeInclusive<u16>>,
+
+    /// Number of instructions written to the trace on an error
+    #[structopt(long, default_value = "64")]
+    pub trace_history: usize,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct DisasmOptions {
+    /// Path to the ROM to disassemble
+    #[structopt(parse(from_os_str))]
+    pub rom: PathBuf,
+
+    /// Mnemonics to use: octo or classic
+    #[structopt(long, default_value = "octo", parse(try_from_str = parse_syntax))]
+    pub syntax: Syntax,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct AsmOptions {
+    /// Path to the assembly source
+    #[structopt(parse(from_os_str))]
+    pub source: PathBuf,
+
+    /// Where to write the ROM [default: the source path with a `ch8`
+    /// extension]
+    #[structopt(short, long, parse(from_os_str))]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ReplayOptions {
+    /// Path to a movie recorded with `run --record`
+    #[structopt(parse(from_os_str))]
+    pub movie: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct HashOptions {
+    /// Path to the ROM, or to an Octo program with an `8o` extension to
+    /// compile first
+    #[structopt(parse(from_os_str))]
+    pub rom: PathBuf,
+
+    #[structopt(flatten)]
+    pub machine: MachineOptions,
+}
+
+impl AsmOptions {
+    pub fn output_path(&self) -> PathBuf {
+        self.output
+            .clone()
+            .unwrap_or_else(|| self.source.with_extension("ch8"))
+    }
+}
+
+fn parse_syntax(name: &str) -> Result<Syntax, String> {
+    match name {
+        "octo" => Ok(Syntax::Octo),
+        "classic" => Ok(Syntax::Classic),
+        _ => Err(format!("unknown syntax `{}`", name)),
+    }
+}
+
+/// Parses a range of hex addresses such as `200-2FF`.
+fn parse_range(s: &str) -> Result<RangeInclusive<u16>, String> {
+    let address = |s: &str| {
+        let digits = s.trim_start_matches("0x");
+        u16::from_str_radix(digits, 16).map_err(|_| format!("expected a hex address, got `{}`", s))
+    };
+    let mut parts = s.splitn(2, '-');
+    let start = address(parts.next().unwrap_or_default())?;
+    let end = parts.next().map_or(Ok(start), address)?;
+    if end < start {
+        return Err("the end of the range comes before its start".to_string());
+    }
+    Ok(start..=end)
+}
+
+fn parse_profile(name: &str) -> Result<Quirks, String> {
+    match name {
+        "default" => Ok(Quirks::default()),
+        "vip" => Ok(Quirks::COSMAC_VIP),
+        "chip48" => Ok(Quirks::CHIP_48),
+        "schip" => Ok(Quirks::SUPER_CHIP),
+        "xochip" => Ok(Quirks::XO_CHIP),
+        _ => Err(format!("unknown quirk profile `{}`", name)),
+    }
+}
+
+#[derive(Debug)]
+pub enum QuirkOverride {
+    ShiftInPlace(bool),
+    LoadStoreIncrement(IndexIncrement),
+    JumpUsesVx(bool),
+    LogicResetsVf(bool),
+    ClipSprites(bool),
+    AddToISetsVf(bool),
+    DisplayWait(bool),
+    ExtendedMemory(bool),
+    VipHires(bool),
+    VipLikeRandom(bool),
+    KeyWaitOnPress(bool),
+    SuperChipInstructions(bool),
+    XoChipInstructions(bool),
+}
+
+impl QuirkOverride {
+    fn apply(&self, quirks: &mut Quirks) {
+        match *self {
+            Self::ShiftInPlace(value) => quirks.shift_in_place = value,
+            Self::LoadStoreIncrement(value) => quirks.load_store_increment = value,
+            Self::JumpUsesVx(value) => quirks.jump_uses_vx = value,
+            Self::LogicResetsVf(value) => quirks.logic_resets_vf = value,
+            Self::ClipSprites(value) => quirks.clip_sprites = value,
+            Self::AddToISetsVf(value) => quirks.add_to_i_sets_vf = value,
+            Self::DisplayWait(value) => quirks.display_wait = value,
+            Self::ExtendedMemory(value) => quirks.extended_memory = value,
+            Self::VipHires(value) => quirks.vip_hires = value,
+            Self::VipLikeRandom(value) => quirks.vip_like_random = value,
+            Self::KeyWaitOnPress(value) => quirks.key_wait_on_press = value,
+            Self::SuperChipInstructions(value) => quirks.super_chip_instructions = value,
+            Self::XoChipInstructions(value) => quirks.xo_chip_instructions = value,
+        }
+    }
+}
+
+impl FromStr for QuirkOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '=');
+        let name = parts.next().unwrap_or_default();
+        let value = parts
+            .next()
+            .ok_or_else(|| format!("expected NAME=VALUE, got `{}`", s))?;
+        let flag = || {
+            value
+                .parse::<bool>()
+                .map_err(|_| format!("expected true or false for `{}`, got `{}`", name, value))
+        };
+        match name {
+            "shift_in_place" => flag().map(Self::ShiftInPlace),
+            "load_store_increment" => match value {
+                "none" => Ok(Self::LoadStoreIncrement(IndexIncrement::Unchanged)),
+                "x" => Ok(Self::LoadStoreIncrement(IndexIncrement::X)),
+                "x+1" => Ok(Self::LoadStoreIncrement(IndexIncrement::XPlusOne)),
+                _ => Err(format!(
+                    "expected none, x or x+1 for `{}`, got `{}`",
+                    name, value
+                )),
+            },
+            "jump_uses_vx" => flag().map(Self::JumpUsesVx),
+            "logic_resets_vf" => flag().map(Self::LogicResetsVf),
+            "clip_sprites" => flag().map(Self::ClipSprites),
+            "add_to_i_sets_vf" => flag().map(Self::AddToISetsVf),
+            "display_wait" => flag().map(Self::DisplayWait),
+            "extended_memory" => flag().map(Self::ExtendedMemory),
+            "vip_hires" => flag().map(Self::VipHires),
+            "vip_like_random" => flag().map(Self::VipLikeRandom),
+            "key_wait_on_press" => flag().map(Self::KeyWaitOnPress),
+            "super_chip_instructions" => flag().map(Self::SuperChipInstructions),
+            "xo_chip_instructions" => flag().map(Self::XoChipInstructions),
+            _ => Err(format!("unknown quirk `{}`", name)),
+        }
+    }
+}
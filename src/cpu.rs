@@ -1,55 +1,450 @@
 use std::convert::TryInto;
 
-use rand::prelude::*;
-
-use crate::display::Display;
+use crate::display::{self, Display};
+use crate::error::Chip8Error;
 use crate::input::Input;
+use crate::quirks::{IndexIncrement, Quirks};
+use crate::rng::Rng;
+use crate::state::{SaveStateError, StateReader, StateWriter};
+
+const MEMORY_SIZE: usize = 0x1000;
+const EXTENDED_MEMORY_SIZE: usize = 0x10000;
+pub(crate) const PROGRAM_START: usize = 0x200;
+const STACK_SIZE: usize = 16;
+const BIG_CHARACTER_SPRITES_START: usize = 0x50;
+const DEFAULT_PITCH: u8 = 64;
+// COSMAC VIP hires programs carry a patch for the interpreter at 0x200-0x2BF
+// and start by jumping into it at 0x260; the patched interpreter then runs
+// the program from 0x2C0.
+const VIP_HIRES_JUMP: u16 = 0x260;
+const VIP_HIRES_START: u16 = 0x2C0;
+const VIP_HIRES_CLEAR_SCREEN: u16 = 0x230;
 
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone)]
 pub struct CPU {
-    memory: [u8; 4096],
+    memory: Vec<u8>,
     registers: [u8; 16],
     address_register: u16, // "Register I"
     instruction_pointer: u16,
     stack: Vec<u16>,
     delay_timer: u8,
     sound_timer: u8,
+    quirks: Quirks,
+    vblank: bool,
+    vip_hires: bool,
+    rpl_flags: [u8; 16],
+    audio_pattern: Option<[u8; 16]>,
+    pitch: u8,
+    exited: bool,
+    error: Option<Chip8Error>,
+    rng: Rng,
+    /// Memory accesses made by the last instruction, when tracing is on.
+    memory_accesses: Option<Vec<MemoryAccess>>,
+}
+
+/// How an instruction touched memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    /// Fetched as part of an instruction.
+    Execute,
+}
+
+/// One byte of memory read, written or executed by an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub kind: AccessKind,
+    pub address: u16,
+    /// The value before the access.
+    pub old: u8,
+    /// The value after the access, the same as `old` unless it is a write.
+    pub new: u8,
 }
 
 impl CPU {
-    pub fn new(program: &[u8]) -> Self {
-        let mut memory = [0; 4096];
+    pub fn new(program: &[u8], quirks: Quirks) -> Result<Self, Chip8Error> {
+        let memory_size = if quirks.extended_memory {
+            EXTENDED_MEMORY_SIZE
+        } else {
+            MEMORY_SIZE
+        };
+        if program.len() > memory_size - PROGRAM_START {
+            return Err(Chip8Error::ProgramTooLarge {
+                size: program.len(),
+                max: memory_size - PROGRAM_START,
+            });
+        }
+        let mut memory = vec![0; memory_size];
         let character_sprite_data = CHARACTER_SPRITES
             .iter()
             .flatten()
             .copied()
             .collect::<Vec<_>>();
         memory[0..character_sprite_data.len()].copy_from_slice(&character_sprite_data);
-        memory[0x200..0x200 + program.len()].copy_from_slice(program);
-        Self {
+        let big_character_sprite_data = BIG_CHARACTER_SPRITES
+            .iter()
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+        memory[BIG_CHARACTER_SPRITES_START
+            ..BIG_CHARACTER_SPRITES_START + big_character_sprite_data.len()]
+            .copy_from_slice(&big_character_sprite_data);
+        memory[PROGRAM_START..PROGRAM_START + program.len()].copy_from_slice(program);
+        Ok(Self {
             memory,
             registers: [0; 16],
             address_register: 0,
-            instruction_pointer: 0x200,
+            instruction_pointer: PROGRAM_START as u16,
             delay_timer: 0,
             sound_timer: 0,
-            stack: Vec::with_capacity(16),
+            stack: Vec::with_capacity(STACK_SIZE),
+            quirks,
+            vblank: false,
+            vip_hires: false,
+            rpl_flags: [0; 16],
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            exited: false,
+            error: None,
+            rng: Rng::new(rand::random()),
+            memory_accesses: None,
+        })
+    }
+
+    /// Restarts the random number generator behind CXNN from `seed`. A new
+    /// CPU starts from a random seed.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    pub(crate) fn write_state(&self, writer: &mut StateWriter) {
+        writer.quirks(&self.quirks);
+        writer.sized_bytes(&self.memory);
+        writer.bytes(&self.registers);
+        writer.u16(self.address_register);
+        writer.u16(self.instruction_pointer);
+        writer.u8(self.stack.len() as u8);
+        for &address in &self.stack {
+            writer.u16(address);
+        }
+        writer.u8(self.delay_timer);
+        writer.u8(self.sound_timer);
+        writer.bool(self.vblank);
+        writer.bool(self.vip_hires);
+        writer.bytes(&self.rpl_flags);
+        writer.bool(self.audio_pattern.is_some());
+        if let Some(pattern) = &self.audio_pattern {
+            writer.bytes(pattern);
+        }
+        writer.u8(self.pitch);
+        writer.bool(self.exited);
+        writer.error(self.error.as_ref());
+        let (pcg, vip) = self.rng.state();
+        writer.u64(pcg);
+        writer.u16(vip);
+    }
+
+    /// Restores a CPU saved with `write_state`. Memory tracing is not part of
+    /// the state and stays as it is.
+    pub(crate) fn read_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        let quirks = reader.quirks()?;
+        let memory = reader.sized_bytes()?;
+        let expected_size = if quirks.extended_memory {
+            EXTENDED_MEMORY_SIZE
+        } else {
+            MEMORY_SIZE
+        };
+        if memory.len() != expected_size {
+            return Err(SaveStateError::Corrupt);
         }
+        let registers = reader.array()?;
+        let address_register = reader.u16()?;
+        let instruction_pointer = reader.u16()?;
+        let stack_len = reader.u8()? as usize;
+        if stack_len > STACK_SIZE {
+            return Err(SaveStateError::Corrupt);
+        }
+        let stack = (0..stack_len)
+            .map(|_| reader.u16())
+            .collect::<Result<Vec<_>, _>>()?;
+        *self = Self {
+            memory: memory.to_vec(),
+            registers,
+            address_register,
+            instruction_pointer,
+            stack,
+            delay_timer: reader.u8()?,
+            sound_timer: reader.u8()?,
+            quirks,
+            vblank: reader.bool()?,
+            vip_hires: reader.bool()?,
+            rpl_flags: reader.array()?,
+            audio_pattern: if reader.bool()? {
+                Some(reader.array()?)
+            } else {
+                None
+            },
+            pitch: reader.u8()?,
+            exited: reader.bool()?,
+            error: reader.error()?,
+            rng: Rng::from_state(reader.u64()?, reader.u16()?),
+            memory_accesses: self.memory_accesses.take(),
+        };
+        Ok(())
     }
 
-    pub fn cycle(&mut self, display: &mut Display, input: &Input) {
-        let i = self.instruction_pointer as usize;
-        let opcode = u16::from_be_bytes(self.memory[i..i + 2].try_into().unwrap());
-        let instruction = Instruction::from_opcode(opcode);
-        self.execute_instruction(instruction, display, input);
+    /// Executes one instruction.
+    ///
+    /// An error halts the CPU: it is returned from this and every later call
+    /// without executing anything, and the machine state is left as it was
+    /// just before the failing instruction.
+    pub fn cycle(&mut self, display: &mut Display, input: &Input) -> Result<(), Chip8Error> {
+        if let Some(error) = &self.error {
+            return Err(error.clone());
+        }
+        if self.exited {
+            return Ok(());
+        }
+        if let Some(accesses) = &mut self.memory_accesses {
+            accesses.clear();
+        }
+        let result = self
+            .fetch()
+            .and_then(|instruction| self.execute_instruction(instruction, display, input));
+        if let Err(error) = &result {
+            self.error = Some(error.clone());
+        }
+        result
     }
 
     pub fn tick(&mut self) {
+        if self.is_halted() {
+            return;
+        }
         self.delay_timer = self.delay_timer.saturating_sub(1);
         self.sound_timer = self.sound_timer.saturating_sub(1);
+        self.vblank = true;
     }
 
     pub fn should_play_sound(&self) -> bool {
-        self.sound_timer > 0
+        !self.is_halted() && self.sound_timer > 0
+    }
+
+    /// Whether the CPU has stopped, either with an error or by executing
+    /// 00FD.
+    pub fn is_halted(&self) -> bool {
+        self.error.is_some() || self.exited
+    }
+
+    /// Whether the program stopped itself with 00FD.
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    /// The SUPER-CHIP RPL user flags written by FX75 and read by FX85.
+    pub fn rpl_flags(&self) -> &[u8; 16] {
+        &self.rpl_flags
+    }
+
+    pub fn set_rpl_flags(&mut self, flags: [u8; 16]) {
+        self.rpl_flags = flags;
+    }
+
+    /// The 1-bit, 128-sample XO-CHIP audio pattern loaded by F002, if any.
+    pub fn audio_pattern(&self) -> Option<&[u8; 16]> {
+        self.audio_pattern.as_ref()
+    }
+
+    /// The rate at which the audio pattern is played, in samples per second.
+    pub fn audio_sample_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    /// The error that halted the CPU, if any.
+    pub fn error(&self) -> Option<&Chip8Error> {
+        self.error.as_ref()
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    /// The value of register I.
+    pub fn address_register(&self) -> u16 {
+        self.address_register
+    }
+
+    pub fn instruction_pointer(&self) -> u16 {
+        self.instruction_pointer
+    }
+
+    /// The return addresses of the active subroutines, innermost last.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    // Setters for the GDB stub, which lets the client change the machine.
+
+    pub(crate) fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    pub(crate) fn registers_mut(&mut self) -> &mut [u8; 16] {
+        &mut self.registers
+    }
+
+    pub(crate) fn set_address_register(&mut self, value: u16) {
+        self.address_register = value;
+    }
+
+    pub(crate) fn set_instruction_pointer(&mut self, value: u16) {
+        self.instruction_pointer = value;
+    }
+
+    pub(crate) fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub(crate) fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    /// The opcode at the instruction pointer, or `None` if it lies outside
+    /// memory.
+    pub fn current_opcode(&self) -> Option<u16> {
+        let address = self.instruction_pointer as usize;
+        let bytes = self.memory.get(address..address + 2)?;
+        Some(u16::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// Turns recording of the memory accesses made by each instruction on or
+    /// off.
+    pub fn trace_memory(&mut self, enabled: bool) {
+        if enabled != self.memory_accesses.is_some() {
+            self.memory_accesses = if enabled { Some(Vec::new()) } else { None };
+        }
+    }
+
+    /// The memory accesses made by the last instruction, in order. Always
+    /// empty unless tracing is on.
+    pub fn memory_accesses(&self) -> &[MemoryAccess] {
+        self.memory_accesses.as_deref().unwrap_or_default()
+    }
+
+    fn fetch(&mut self) -> Result<Instruction, Chip8Error> {
+        let address = self.instruction_pointer;
+        let opcode = self.fetch_word(address as usize)?;
+        let instruction = Instruction::from_opcode(opcode)
+            .ok_or(Chip8Error::InvalidOpcode { address, opcode })?;
+        Ok(match instruction {
+            // Before SUPER-CHIP these were calls to machine code routines
+            Instruction::ScrollDown { .. }
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::Exit
+            | Instruction::LowRes
+            | Instruction::HighRes
+                if !self.quirks.super_chip_instructions =>
+            {
+                Instruction::RCA1802 {
+                    address: opcode & 0xFFF,
+                }
+            }
+            Instruction::ScrollUp { .. } if !self.quirks.xo_chip_instructions => {
+                Instruction::RCA1802 {
+                    address: opcode & 0xFFF,
+                }
+            }
+            Instruction::SaveRange { .. }
+            | Instruction::LoadRange { .. }
+            | Instruction::SetILong
+            | Instruction::SelectPlanes { .. }
+            | Instruction::LoadAudio
+            | Instruction::SetPitch { .. }
+                if !self.quirks.xo_chip_instructions =>
+            {
+                return Err(Chip8Error::InvalidOpcode { address, opcode });
+            }
+            instruction => instruction,
+        })
+    }
+
+    fn fetch_word(&mut self, start: usize) -> Result<u16, Chip8Error> {
+        let bytes = self.access_memory(start, 2, AccessKind::Execute)?;
+        Ok(u16::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn read_memory(&mut self, start: usize, len: usize) -> Result<&[u8], Chip8Error> {
+        self.access_memory(start, len, AccessKind::Read)
+    }
+
+    fn access_memory(
+        &mut self,
+        start: usize,
+        len: usize,
+        kind: AccessKind,
+    ) -> Result<&[u8], Chip8Error> {
+        self.check_bounds(start, len)?;
+        let bytes = &self.memory[start..start + len];
+        if let Some(accesses) = &mut self.memory_accesses {
+            accesses.extend(bytes.iter().enumerate().map(|(i, &value)| MemoryAccess {
+                kind,
+                address: (start + i) as u16,
+                old: value,
+                new: value,
+            }));
+        }
+        Ok(bytes)
+    }
+
+    fn write_memory(&mut self, start: usize, data: &[u8]) -> Result<(), Chip8Error> {
+        self.check_bounds(start, data.len())?;
+        let bytes = &mut self.memory[start..start + data.len()];
+        if let Some(accesses) = &mut self.memory_accesses {
+            accesses.extend(bytes.iter().zip(data).enumerate().map(|(i, (&old, &new))| {
+                MemoryAccess {
+                    kind: AccessKind::Write,
+                    address: (start + i) as u16,
+                    old,
+                    new,
+                }
+            }));
+        }
+        bytes.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn check_bounds(&self, start: usize, len: usize) -> Result<(), Chip8Error> {
+        let memory_size = self.memory.len();
+        if start + len > memory_size {
+            return Err(Chip8Error::MemoryOutOfBounds {
+                address: self.instruction_pointer,
+                location: start.max(memory_size),
+            });
+        }
+        Ok(())
+    }
+
+    /// Skips the next instruction, which takes two words if it is F000 NNNN.
+    fn skip_next_instruction(&mut self) {
+        let next = self.instruction_pointer as usize + 2;
+        let long = self.quirks.xo_chip_instructions
+            && self.memory.get(next..next + 2) == Some(&[0xF0, 0x00]);
+        self.advance(if long { 6 } else { 4 });
     }
 
     fn execute_instruction(
@@ -57,16 +452,38 @@ impl CPU {
         instruction: Instruction,
         display: &mut Display,
         input: &Input,
-    ) {
+    ) -> Result<(), Chip8Error> {
         let mut jump = false;
         match instruction {
+            Instruction::RCA1802 {
+                address: VIP_HIRES_CLEAR_SCREEN,
+            } if self.vip_hires => display.clear(),
             Instruction::RCA1802 { .. } => {}
             Instruction::ClearScreen => {
                 display.clear();
             }
+            Instruction::ScrollDown { rows } => display.scroll_down(rows as usize),
+            Instruction::ScrollUp { rows } => display.scroll_up(rows as usize),
+            Instruction::ScrollRight => display.scroll_right(4),
+            Instruction::ScrollLeft => display.scroll_left(4),
+            Instruction::Exit => {
+                self.exited = true;
+                jump = true;
+            }
+            Instruction::LowRes => display.resize(display::WIDTH, display::HEIGHT),
+            Instruction::HighRes => display.resize(display::HIRES_WIDTH, display::HIRES_HEIGHT),
             Instruction::Return => {
-                self.instruction_pointer =
-                    self.stack.pop().expect("Return called with empty stack");
+                self.instruction_pointer = self.stack.pop().ok_or(Chip8Error::StackUnderflow {
+                    address: self.instruction_pointer,
+                })?;
+                jump = true;
+            }
+            Instruction::Jump {
+                address: VIP_HIRES_JUMP,
+            } if self.quirks.vip_hires && self.instruction_pointer == PROGRAM_START as u16 => {
+                self.vip_hires = true;
+                display.resize(display::VIP_HIRES_WIDTH, display::VIP_HIRES_HEIGHT);
+                self.instruction_pointer = VIP_HIRES_START;
                 jump = true;
             }
             Instruction::Jump { address } => {
@@ -74,36 +491,53 @@ impl CPU {
                 jump = true;
             }
             Instruction::Subroutine { address } => {
-                self.stack.push(self.instruction_pointer + 2);
+                if self.stack.len() == STACK_SIZE {
+                    return Err(Chip8Error::StackOverflow {
+                        address: self.instruction_pointer,
+                    });
+                }
+                self.stack.push(self.instruction_pointer.wrapping_add(2));
                 self.instruction_pointer = address;
                 jump = true;
             }
             Instruction::IfEqualConst { register, value } => {
                 if self.registers[register as usize] == value {
-                    self.instruction_pointer += 4;
+                    self.skip_next_instruction();
                     jump = true;
                 }
             }
             Instruction::IfNotEqualConst { register, value } => {
                 if self.registers[register as usize] != value {
-                    self.instruction_pointer += 4;
+                    self.skip_next_instruction();
                     jump = true;
                 }
             }
             Instruction::IfEqualRegister { a, b } => {
                 if self.registers[a as usize] == self.registers[b as usize] {
-                    self.instruction_pointer += 4;
+                    self.skip_next_instruction();
                     jump = true;
                 }
             }
             Instruction::SetConst { register, value } => self.registers[register as usize] = value,
-            Instruction::AddConst { register, value } => self.registers[register as usize] += value,
+            Instruction::AddConst { register, value } => {
+                let register = &mut self.registers[register as usize];
+                *register = register.wrapping_add(value);
+            }
             Instruction::SetRegister { dest, src } => {
                 self.registers[dest as usize] = self.registers[src as usize]
             }
-            Instruction::Or { a, b } => self.registers[a as usize] |= self.registers[b as usize],
-            Instruction::And { a, b } => self.registers[a as usize] &= self.registers[b as usize],
-            Instruction::Xor { a, b } => self.registers[a as usize] ^= self.registers[b as usize],
+            Instruction::Or { a, b } => {
+                self.registers[a as usize] |= self.registers[b as usize];
+                self.reset_vf_after_logic();
+            }
+            Instruction::And { a, b } => {
+                self.registers[a as usize] &= self.registers[b as usize];
+                self.reset_vf_after_logic();
+            }
+            Instruction::Xor { a, b } => {
+                self.registers[a as usize] ^= self.registers[b as usize];
+                self.reset_vf_after_logic();
+            }
             Instruction::Add { a, b } => {
                 let a_val = self.registers[a as usize];
                 let b_val = self.registers[b as usize];
@@ -119,10 +553,10 @@ impl CPU {
                 // 1 if NOT overflow
                 self.registers[15] = if !overflow { 1 } else { 0 };
             }
-            Instruction::ShiftRight { register } => {
-                let val = self.registers[register as usize];
+            Instruction::ShiftRight { a, b } => {
+                let val = self.registers[self.shift_source(a, b) as usize];
+                self.registers[a as usize] = val >> 1;
                 self.registers[15] = val & 1;
-                self.registers[register as usize] >>= 1;
             }
             Instruction::NegSub { a, b } => {
                 let a_val = self.registers[a as usize];
@@ -132,65 +566,115 @@ impl CPU {
                 // 1 if NOT overflow
                 self.registers[15] = if !overflow { 1 } else { 0 };
             }
-            Instruction::ShiftLeft { register } => {
-                let val = self.registers[register as usize];
+            Instruction::ShiftLeft { a, b } => {
+                let val = self.registers[self.shift_source(a, b) as usize];
+                self.registers[a as usize] = val << 1;
                 self.registers[15] = val >> 7;
-                self.registers[register as usize] <<= 1;
             }
             Instruction::IfNotEqualRegister { a, b } => {
                 if self.registers[a as usize] != self.registers[b as usize] {
-                    self.instruction_pointer += 4;
+                    self.skip_next_instruction();
                     jump = true;
                 }
             }
             Instruction::SetI { address } => self.address_register = address,
+            Instruction::SetILong => {
+                let i = self.instruction_pointer as usize + 2;
+                self.address_register = self.fetch_word(i)?;
+                self.advance(4);
+                jump = true;
+            }
+            Instruction::SelectPlanes { planes } => display.select_planes(planes),
+            Instruction::LoadAudio => {
+                let i = self.address_register as usize;
+                let pattern = self.read_memory(i, 16)?.try_into().unwrap();
+                self.audio_pattern = Some(pattern);
+            }
+            Instruction::SetPitch { register } => self.pitch = self.registers[register as usize],
+            Instruction::SaveRange { a, b } => {
+                let start = self.address_register as usize;
+                let data: Vec<u8> = register_range(a, b)
+                    .into_iter()
+                    .map(|register| self.registers[register])
+                    .collect();
+                self.write_memory(start, &data)?;
+            }
+            Instruction::LoadRange { a, b } => {
+                let start = self.address_register as usize;
+                let range = register_range(a, b);
+                let data = self.read_memory(start, range.len())?.to_vec();
+                for (register, value) in range.into_iter().zip(data) {
+                    self.registers[register] = value;
+                }
+            }
             Instruction::JumpOffset { address } => {
-                self.instruction_pointer = address + self.registers[0] as u16;
+                let register = if self.quirks.jump_uses_vx {
+                    (address >> 8) as usize
+                } else {
+                    0
+                };
+                self.instruction_pointer = address + self.registers[register] as u16;
                 jump = true;
             }
             Instruction::Rand { register, value } => {
-                let mut rng = rand::thread_rng();
-                self.registers[register as usize] = rng.gen::<u8>() & value;
+                let random = if self.quirks.vip_like_random {
+                    // The fonts stand in for the VIP interpreter's code page
+                    self.rng.next_vip_like_byte(&self.memory[..0x100])
+                } else {
+                    self.rng.next_byte()
+                };
+                self.registers[register as usize] = random & value;
             }
             Instruction::DrawSprite {
                 x: x_register,
                 y: y_register,
                 height,
             } => {
-                let i = self.address_register as usize;
-                let start_x = self.registers[x_register as usize];
-                let start_y = self.registers[y_register as usize];
-                let n = height as usize;
-                let sprite = &self.memory[i..i + n];
+                if self.quirks.display_wait && !self.vblank {
+                    // Stay on this instruction until the next tick
+                    return Ok(());
+                }
+                // DXY0 draws a 16x16 sprite stored as two bytes per row on
+                // SUPER-CHIP, and nothing before it
+                let (sprite_width, n) = match height {
+                    0 if self.quirks.super_chip_instructions => (16, 32),
+                    _ => (8, height as usize),
+                };
+                // Each selected plane gets its own sprite, one after another
+                let planes = display.selected_planes();
                 let (display_width, display_height) = display.dimensions();
+                let x = self.registers[x_register as usize] % display_width;
+                let y = self.registers[y_register as usize] % display_height;
+                let clip = self.quirks.clip_sprites;
+                let i = self.address_register as usize;
+                let sprites = self.read_memory(i, n * planes.count_ones() as usize)?;
                 let mut collision = false;
-                for (i, &byte) in sprite.iter().enumerate() {
-                    let mut mask = 1 << 7;
-                    for j in 0..8 {
-                        let x = (start_x + j) % display_width;
-                        let y = (start_y + i as u8) % display_height;
-                        let value = mask & byte > 0;
-                        let prev_value = display.get_pixel(x, y);
-                        if value && prev_value {
-                            collision = true;
-                        }
-                        display.set_pixel(x, y, value ^ prev_value);
-                        mask >>= 1;
-                    }
+                let mut sprites = sprites.chunks(n.max(1));
+                for plane in [0b01, 0b10].iter().filter(|&plane| planes & plane != 0) {
+                    let sprite = Sprite {
+                        data: sprites.next().unwrap_or_default(),
+                        width: sprite_width,
+                        x,
+                        y,
+                    };
+                    collision |= sprite.draw(display, *plane, clip);
                 }
+                // Only once the sprite was read, so that a failed draw
+                // changes nothing
+                self.vblank = false;
                 self.registers[15] = collision as u8;
             }
             Instruction::IfPressed { register } => {
-                let key = self.registers[register as usize];
+                let key = self.registers[register as usize] & 0xF;
                 if input.is_key_pressed(key) {
-                    self.instruction_pointer += 4;
+                    self.skip_next_instruction();
                     jump = true;
                 }
             }
             Instruction::IfNotPressed { register } => {
-                let key = self.registers[register as usize];
+                let key = self.registers[register as usize] & 0xF;
                 if !input.is_key_pressed(key) {
-                    self.instruction_pointer += 4;
+                    self.skip_next_instruction();
                     jump = true;
                 }
             }
@@ -199,9 +683,15 @@ impl CPU {
             }
             Instruction::AwaitInput { register } => {
                 jump = true;
-                if let Some(key) = input.get_pressed_key() {
+                let key = if self.quirks.key_wait_on_press {
+                    input.get_pressed_key()
+                } else {
+                    // The COSMAC VIP waits for the key to be let go again
+                    input.get_released_key()
+                };
+                if let Some(key) = key {
                     self.registers[register as usize] = key;
-                    self.instruction_pointer += 2;
+                    self.advance(2);
                 }
             }
             Instruction::SetTimer { register } => {
@@ -211,38 +701,135 @@ impl CPU {
                 self.sound_timer = self.registers[register as usize]
             }
             Instruction::AddToI { register } => {
-                self.address_register += self.registers[register as usize] as u16
+                let sum = self
+                    .address_register
+                    .wrapping_add(self.registers[register as usize] as u16);
+                if self.quirks.add_to_i_sets_vf {
+                    self.registers[15] = (sum > 0xFFF) as u8;
+                }
+                self.address_register = sum;
             }
             Instruction::SetIToFontChar { register } => {
-                let val = self.registers[register as usize];
-                assert!(val <= 16);
+                let val = self.registers[register as usize] & 0xF;
                 self.address_register = val as u16 * 5;
             }
+            Instruction::SetIToBigFontChar { register } => {
+                let val = self.registers[register as usize] & 0xF;
+                self.address_register = (BIG_CHARACTER_SPRITES_START + val as usize * 10) as u16;
+            }
             Instruction::BinaryCodedDecimal { register } => {
                 let val = self.registers[register as usize];
                 let i = self.address_register as usize;
-                self.memory[i] = val / 100;
-                self.memory[i + 1] = val % 100 / 10;
-                self.memory[i + 2] = val % 10;
+                self.write_memory(i, &[val / 100, val % 100 / 10, val % 10])?;
             }
             Instruction::RegisterDump { register } => {
                 let start = self.address_register as usize;
-                let n = register as usize;
-                for i in 0..=n {
-                    self.memory[start + i] = self.registers[i];
-                }
+                let n = register as usize + 1;
+                let registers = self.registers;
+                self.write_memory(start, &registers[..n])?;
+                self.increment_index_after_load_store(register);
             }
             Instruction::RegisterLoad { register } => {
                 let start = self.address_register as usize;
-                let n = register as usize;
-                for i in 0..=n {
-                    self.registers[i] = self.memory[start + i];
-                }
+                let n = register as usize + 1;
+                let data = self.read_memory(start, n)?.to_vec();
+                self.registers[..n].copy_from_slice(&data);
+                self.increment_index_after_load_store(register);
+            }
+            Instruction::SaveFlags { register } => {
+                let n = register as usize + 1;
+                self.rpl_flags[..n].copy_from_slice(&self.registers[..n]);
+            }
+            Instruction::LoadFlags { register } => {
+                let n = register as usize + 1;
+                self.registers[..n].copy_from_slice(&self.rpl_flags[..n]);
             }
         };
         if !jump {
-            self.instruction_pointer += 2;
+            self.advance(2);
+        }
+        Ok(())
+    }
+
+    /// Moves the instruction pointer forward by `bytes`. Like I, it wraps
+    /// around at the top of the 16-bit address space.
+    fn advance(&mut self, bytes: u16) {
+        self.instruction_pointer = self.instruction_pointer.wrapping_add(bytes);
+    }
+
+    fn reset_vf_after_logic(&mut self) {
+        if self.quirks.logic_resets_vf {
+            self.registers[15] = 0;
+        }
+    }
+
+    fn shift_source(&self, a: u8, b: u8) -> u8 {
+        if self.quirks.shift_in_place {
+            a
+        } else {
+            b
+        }
+    }
+
+    fn increment_index_after_load_store(&mut self, register: u8) {
+        let increment = match self.quirks.load_store_increment {
+            IndexIncrement::Unchanged => 0,
+            IndexIncrement::X => register as u16,
+            IndexIncrement::XPlusOne => register as u16 + 1,
+        };
+        self.address_register = self.address_register.wrapping_add(increment);
+    }
+}
+
+fn register_range(a: u8, b: u8) -> Vec<usize> {
+    let (a, b) = (a as usize, b as usize);
+    if a <= b {
+        (a..=b).collect()
+    } else {
+        (b..=a).rev().collect()
+    }
+}
+
+struct Sprite<'a> {
+    data: &'a [u8],
+    width: usize,
+    x: u8,
+    y: u8,
+}
+
+impl Sprite<'_> {
+    /// XORs the sprite onto one plane of the display, returning whether any
+    /// lit pixel was turned off.
+    fn draw(&self, display: &mut Display, plane: u8, clip: bool) -> bool {
+        let (display_width, display_height) = display.dimensions();
+        let (display_width, display_height) = (display_width as usize, display_height as usize);
+        let bytes_per_row = self.width / 8;
+        let mut collision = false;
+        for (i, row) in self.data.chunks(bytes_per_row).enumerate() {
+            let y = self.y as usize + i;
+            if clip && y >= display_height {
+                break;
+            }
+            let y = (y % display_height) as u8;
+            let bits = row.iter().fold(0u16, |bits, &byte| bits << 8 | byte as u16);
+            let mut mask = 1 << (self.width - 1);
+            for j in 0..self.width {
+                let x = self.x as usize + j;
+                if clip && x >= display_width {
+                    break;
+                }
+                let x = (x % display_width) as u8;
+                if mask & bits > 0 {
+                    let prev_value = display.get_pixel(x, y);
+                    if prev_value & plane != 0 {
+                        collision = true;
+                    }
+                    display.set_pixel(x, y, prev_value ^ plane);
+                }
+                mask >>= 1;
+            }
         }
+        collision
     }
 }
 
@@ -272,15 +859,24 @@ impl Opcode {
 // http://devernay.free.fr/hacks/chip8/C8TECH10.HTM#3.1
 // https://en.wikipedia.org/wiki/CHIP-8#Opcode_table
 #[derive(Debug, PartialEq, Clone, Copy)]
-enum Instruction {
+pub enum Instruction {
     RCA1802 { address: u16 },                    //0NNN
+    ScrollDown { rows: u8 },                     //00CN
+    ScrollUp { rows: u8 },                       //00DN
     ClearScreen,                                 //00E0
     Return,                                      //00EE
+    ScrollRight,                                 //00FB
+    ScrollLeft,                                  //00FC
+    Exit,                                        //00FD
+    LowRes,                                      //00FE
+    HighRes,                                     //00FF
     Jump { address: u16 },                       //1NNN
     Subroutine { address: u16 },                 //2NNN
     IfEqualConst { register: u8, value: u8 },    //3XNN
     IfNotEqualConst { register: u8, value: u8 }, //4XNN
     IfEqualRegister { a: u8, b: u8 },            //5XY0
+    SaveRange { a: u8, b: u8 },                  //5XY2
+    LoadRange { a: u8, b: u8 },                  //5XY3
     SetConst { register: u8, value: u8 },        //6XNN
     AddConst { register: u8, value: u8 },        //7XNN
     SetRegister { dest: u8, src: u8 },           //8XY0
@@ -289,9 +885,9 @@ enum Instruction {
     Xor { a: u8, b: u8 },                        //8XY3
     Add { a: u8, b: u8 },                        //8XY4
     Sub { a: u8, b: u8 },                        //8XY5
-    ShiftRight { register: u8 },                 //8XY6
+    ShiftRight { a: u8, b: u8 },                 //8XY6
     NegSub { a: u8, b: u8 },                     //8XY7
-    ShiftLeft { register: u8 },                  //8XYE
+    ShiftLeft { a: u8, b: u8 },                  //8XYE
     IfNotEqualRegister { a: u8, b: u8 },         //9XY0
     SetI { address: u16 },                       //ANNN
     JumpOffset { address: u16 },                 //BNNN
@@ -299,21 +895,41 @@ enum Instruction {
     DrawSprite { x: u8, y: u8, height: u8 },     //DXYN
     IfPressed { register: u8 },                  //EX9E
     IfNotPressed { register: u8 },               //EXA1
+    SetILong,                                    //F000 NNNN
+    SelectPlanes { planes: u8 },                 //FN01
+    LoadAudio,                                   //F002
     GetTimer { register: u8 },                   //FX07
     AwaitInput { register: u8 },                 //FX0A
     SetTimer { register: u8 },                   //FX15
     SetSound { register: u8 },                   //FX18
     AddToI { register: u8 },                     //FX1E
     SetIToFontChar { register: u8 },             //FX29
+    SetIToBigFontChar { register: u8 },          //FX30
     BinaryCodedDecimal { register: u8 },         //FX33
+    SetPitch { register: u8 },                   //FX3A
     RegisterDump { register: u8 },               //FX55
     RegisterLoad { register: u8 },               //FX65
+    SaveFlags { register: u8 },                  //FX75
+    LoadFlags { register: u8 },                  //FX85
 }
 
 impl Instruction {
-    fn from_opcode(opcode: u16) -> Self {
+    /// Decodes an opcode, or returns `None` if it is not a valid instruction.
+    pub fn from_opcode(opcode: u16) -> Option<Self> {
         let opcode = Opcode::new(opcode);
-        match opcode {
+        let instruction = match opcode {
+            Opcode {
+                control: 0,
+                a: 0,
+                b: 0xC,
+                ..
+            } => Self::ScrollDown { rows: opcode.c },
+            Opcode {
+                control: 0,
+                a: 0,
+                b: 0xD,
+                ..
+            } => Self::ScrollUp { rows: opcode.c },
             Opcode {
                 control: 0,
                 a: 0,
@@ -326,6 +942,36 @@ impl Instruction {
                 constant: 0xEE,
                 ..
             } => Self::Return,
+            Opcode {
+                control: 0,
+                a: 0,
+                constant: 0xFB,
+                ..
+            } => Self::ScrollRight,
+            Opcode {
+                control: 0,
+                a: 0,
+                constant: 0xFC,
+                ..
+            } => Self::ScrollLeft,
+            Opcode {
+                control: 0,
+                a: 0,
+                constant: 0xFD,
+                ..
+            } => Self::Exit,
+            Opcode {
+                control: 0,
+                a: 0,
+                constant: 0xFE,
+                ..
+            } => Self::LowRes,
+            Opcode {
+                control: 0,
+                a: 0,
+                constant: 0xFF,
+                ..
+            } => Self::HighRes,
             Opcode { control: 0, .. } => Self::RCA1802 {
                 address: opcode.address,
             },
@@ -349,6 +995,18 @@ impl Instruction {
                 a: opcode.a,
                 b: opcode.b,
             },
+            Opcode {
+                control: 5, c: 2, ..
+            } => Self::SaveRange {
+                a: opcode.a,
+                b: opcode.b,
+            },
+            Opcode {
+                control: 5, c: 3, ..
+            } => Self::LoadRange {
+                a: opcode.a,
+                b: opcode.b,
+            },
             Opcode { control: 6, .. } => Self::SetConst {
                 register: opcode.a,
                 value: opcode.constant,
@@ -395,7 +1053,10 @@ impl Instruction {
             },
             Opcode {
                 control: 8, c: 6, ..
-            } => Self::ShiftRight { register: opcode.a },
+            } => Self::ShiftRight {
+                a: opcode.a,
+                b: opcode.b,
+            },
             Opcode {
                 control: 8, c: 7, ..
             } => Self::NegSub {
@@ -404,7 +1065,10 @@ impl Instruction {
             },
             Opcode {
                 control: 8, c: 0xE, ..
-            } => Self::ShiftLeft { register: opcode.a },
+            } => Self::ShiftLeft {
+                a: opcode.a,
+                b: opcode.b,
+            },
             Opcode {
                 control: 9, c: 0, ..
             } => Self::IfNotEqualRegister {
@@ -436,6 +1100,24 @@ impl Instruction {
                 constant: 0xA1,
                 ..
             } => Self::IfNotPressed { register: opcode.a },
+            Opcode {
+                control: 0xF,
+                a: 0,
+                constant: 0x00,
+                ..
+            } => Self::SetILong,
+            Opcode {
+                control: 0xF,
+                a: 0..=3,
+                constant: 0x01,
+                ..
+            } => Self::SelectPlanes { planes: opcode.a },
+            Opcode {
+                control: 0xF,
+                a: 0,
+                constant: 0x02,
+                ..
+            } => Self::LoadAudio,
             Opcode {
                 control: 0xF,
                 constant: 0x07,
@@ -466,11 +1148,21 @@ impl Instruction {
                 constant: 0x29,
                 ..
             } => Self::SetIToFontChar { register: opcode.a },
+            Opcode {
+                control: 0xF,
+                constant: 0x30,
+                ..
+            } => Self::SetIToBigFontChar { register: opcode.a },
             Opcode {
                 control: 0xF,
                 constant: 0x33,
                 ..
             } => Self::BinaryCodedDecimal { register: opcode.a },
+            Opcode {
+                control: 0xF,
+                constant: 0x3A,
+                ..
+            } => Self::SetPitch { register: opcode.a },
             Opcode {
                 control: 0xF,
                 constant: 0x55,
@@ -481,7 +1173,79 @@ impl Instruction {
                 constant: 0x65,
                 ..
             } => Self::RegisterLoad { register: opcode.a },
-            _ => panic!("invalid opcode"),
+            Opcode {
+                control: 0xF,
+                constant: 0x75,
+                ..
+            } => Self::SaveFlags { register: opcode.a },
+            Opcode {
+                control: 0xF,
+                constant: 0x85,
+                ..
+            } => Self::LoadFlags { register: opcode.a },
+            _ => return None,
+        };
+        Some(instruction)
+    }
+
+    /// Encodes the instruction, the inverse of `from_opcode`. `SetILong`
+    /// encodes as F000; the address it loads is the word that follows.
+    pub fn to_opcode(&self) -> u16 {
+        let xy = |x: u8, y: u8| (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4;
+        let xnn = |x: u8, nn: u8| (x as u16 & 0xF) << 8 | nn as u16;
+        let x = |x: u8| (x as u16 & 0xF) << 8;
+        match *self {
+            Self::RCA1802 { address } => address & 0xFFF,
+            Self::ScrollDown { rows } => 0x00C0 | rows as u16 & 0xF,
+            Self::ScrollUp { rows } => 0x00D0 | rows as u16 & 0xF,
+            Self::ClearScreen => 0x00E0,
+            Self::Return => 0x00EE,
+            Self::ScrollRight => 0x00FB,
+            Self::ScrollLeft => 0x00FC,
+            Self::Exit => 0x00FD,
+            Self::LowRes => 0x00FE,
+            Self::HighRes => 0x00FF,
+            Self::Jump { address } => 0x1000 | address & 0xFFF,
+            Self::Subroutine { address } => 0x2000 | address & 0xFFF,
+            Self::IfEqualConst { register, value } => 0x3000 | xnn(register, value),
+            Self::IfNotEqualConst { register, value } => 0x4000 | xnn(register, value),
+            Self::IfEqualRegister { a, b } => 0x5000 | xy(a, b),
+            Self::SaveRange { a, b } => 0x5002 | xy(a, b),
+            Self::LoadRange { a, b } => 0x5003 | xy(a, b),
+            Self::SetConst { register, value } => 0x6000 | xnn(register, value),
+            Self::AddConst { register, value } => 0x7000 | xnn(register, value),
+            Self::SetRegister { dest, src } => 0x8000 | xy(dest, src),
+            Self::Or { a, b } => 0x8001 | xy(a, b),
+            Self::And { a, b } => 0x8002 | xy(a, b),
+            Self::Xor { a, b } => 0x8003 | xy(a, b),
+            Self::Add { a, b } => 0x8004 | xy(a, b),
+            Self::Sub { a, b } => 0x8005 | xy(a, b),
+            Self::ShiftRight { a, b } => 0x8006 | xy(a, b),
+            Self::NegSub { a, b } => 0x8007 | xy(a, b),
+            Self::ShiftLeft { a, b } => 0x800E | xy(a, b),
+            Self::IfNotEqualRegister { a, b } => 0x9000 | xy(a, b),
+            Self::SetI { address } => 0xA000 | address & 0xFFF,
+            Self::JumpOffset { address } => 0xB000 | address & 0xFFF,
+            Self::Rand { register, value } => 0xC000 | xnn(register, value),
+            Self::DrawSprite { x, y, height } => 0xD000 | xy(x, y) | height as u16 & 0xF,
+            Self::IfPressed { register } => 0xE09E | x(register),
+            Self::IfNotPressed { register } => 0xE0A1 | x(register),
+            Self::SetILong => 0xF000,
+            Self::SelectPlanes { planes } => 0xF001 | x(planes & 0b11),
+            Self::LoadAudio => 0xF002,
+            Self::GetTimer { register } => 0xF007 | x(register),
+            Self::AwaitInput { register } => 0xF00A | x(register),
+            Self::SetTimer { register } => 0xF015 | x(register),
+            Self::SetSound { register } => 0xF018 | x(register),
+            Self::AddToI { register } => 0xF01E | x(register),
+            Self::SetIToFontChar { register } => 0xF029 | x(register),
+            Self::SetIToBigFontChar { register } => 0xF030 | x(register),
+            Self::BinaryCodedDecimal { register } => 0xF033 | x(register),
+            Self::SetPitch { register } => 0xF03A | x(register),
+            Self::RegisterDump { register } => 0xF055 | x(register),
+            Self::RegisterLoad { register } => 0xF065 | x(register),
+            Self::SaveFlags { register } => 0xF075 | x(register),
+            Self::LoadFlags { register } => 0xF085 | x(register),
         }
     }
 }
@@ -504,3 +1268,23 @@ const CHARACTER_SPRITES: [[u8; 5]; 16] = [
     [0xF0, 0x80, 0xF0, 0x80, 0xF0], // E
     [0xF0, 0x80, 0xF0, 0x80, 0x80], // F
 ];
+
+// SUPER-CHIP 1.1 digits, extended with A-F
+const BIG_CHARACTER_SPRITES: [[u8; 10]; 16] = [
+    [0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C], // 0
+    [0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C], // 1
+    [0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF], // 2
+    [0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C], // 3
+    [0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06], // 4
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C], // 5
+    [0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C], // 6
+    [0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60], // 7
+    [0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C], // 8
+    [0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C], // 9
+    [0x3C, 0x7E, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3], // A
+    [0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC], // B
+    [0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C], // C
+    [0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC], // D
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xFF, 0xFF], // E
+    [0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xC0, 0xC0], // F
+];
@@ -0,0 +1,709 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::chip8::Chip8;
+use crate::cpu::{AccessKind, MemoryAccess, CPU};
+use crate::disassembler::{self, Syntax};
+use crate::error::Chip8Error;
+
+const HELP: &str = "\
+commands:
+  c, continue            resume execution
+  p, pause               pause execution
+  s, step [N]            execute N instructions (default 1)
+  f, frame               run until the next timer tick
+  b, break ADDR [if C]   break when PC reaches ADDR and condition C holds
+  b, break if C          break when condition C becomes true
+  w, watch KIND A [B]    break after an instruction accesses memory from A to
+                         B, where KIND is read, write, access or exec
+  d, delete ID           delete a breakpoint or watchpoint
+  l, list                list breakpoints and watchpoints
+  r, regs                show registers, stack and the next instruction
+  h, help                show this help
+conditions have the form REG OP VALUE, where REG is V0-VF, I, PC, SP, DT or ST
+and OP is ==, !=, <, <=, > or >=. Numbers are decimal, or hex with 0x or $.
+an empty line repeats the previous command.";
+
+/// A value a breakpoint condition can look at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+    Pc,
+    /// The number of return addresses on the stack.
+    Sp,
+    DelayTimer,
+    SoundTimer,
+}
+
+impl Register {
+    pub fn read(self, cpu: &CPU) -> u16 {
+        match self {
+            Self::V(x) => cpu.registers()[x as usize] as u16,
+            Self::I => cpu.address_register(),
+            Self::Pc => cpu.instruction_pointer(),
+            Self::Sp => cpu.stack().len() as u16,
+            Self::DelayTimer => cpu.delay_timer() as u16,
+            Self::SoundTimer => cpu.sound_timer() as u16,
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::V(x) => write!(f, "V{:X}", x),
+            Self::I => write!(f, "I"),
+            Self::Pc => write!(f, "PC"),
+            Self::Sp => write!(f, "SP"),
+            Self::DelayTimer => write!(f, "DT"),
+            Self::SoundTimer => write!(f, "ST"),
+        }
+    }
+}
+
+impl FromStr for Register {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_ascii_uppercase();
+        match name.as_str() {
+            "I" => Ok(Self::I),
+            "PC" => Ok(Self::Pc),
+            "SP" => Ok(Self::Sp),
+            "DT" => Ok(Self::DelayTimer),
+            "ST" => Ok(Self::SoundTimer),
+            _ => name
+                .strip_prefix('V')
+                .filter(|x| x.len() == 1)
+                .and_then(|x| u8::from_str_radix(x, 16).ok())
+                .map(Self::V)
+                .ok_or_else(|| format!("unknown register `{}`", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    // Two-character operators come first so `<=` is not read as `<`.
+    const OPERATORS: [(&'static str, Self); 6] = [
+        ("==", Self::Equal),
+        ("!=", Self::NotEqual),
+        ("<=", Self::LessOrEqual),
+        (">=", Self::GreaterOrEqual),
+        ("<", Self::Less),
+        (">", Self::Greater),
+    ];
+
+    fn compare(self, a: u16, b: u16) -> bool {
+        match self {
+            Self::Equal => a == b,
+            Self::NotEqual => a != b,
+            Self::Less => a < b,
+            Self::LessOrEqual => a <= b,
+            Self::Greater => a > b,
+            Self::GreaterOrEqual => a >= b,
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (operator, _) = Self::OPERATORS
+            .iter()
+            .find(|(_, comparison)| comparison == self)
+            .unwrap();
+        write!(f, "{}", operator)
+    }
+}
+
+/// A comparison of a register with a constant, such as `V3 == 5`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub register: Register,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+impl Condition {
+    pub fn holds(&self, cpu: &CPU) -> bool {
+        self.comparison.compare(self.register.read(cpu), self.value)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {:#X}", self.register, self.comparison, self.value)
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (position, operator, comparison) = Comparison::OPERATORS
+            .iter()
+            .filter_map(|&(operator, comparison)| {
+                s.find(operator).map(|i| (i, operator, comparison))
+            })
+            .min_by_key(|&(i, _, _)| i)
+            .ok_or_else(|| format!("expected a comparison, got `{}`", s))?;
+        Ok(Self {
+            register: s[..position].trim().parse()?,
+            comparison,
+            value: parse_number(s[position + operator.len()..].trim())?,
+        })
+    }
+}
+
+/// Pauses execution when the instruction pointer reaches `address` and
+/// `condition` holds. Either can be left out, but not both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub address: Option<u16>,
+    pub condition: Option<Condition>,
+}
+
+impl Breakpoint {
+    fn matches(&self, cpu: &CPU) -> bool {
+        self.address
+            .map_or(true, |address| address == cpu.instruction_pointer())
+            && self
+                .condition
+                .map_or(true, |condition| condition.holds(cpu))
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.address, self.condition) {
+            (Some(address), Some(condition)) => write!(f, "at {:#05X} if {}", address, condition),
+            (Some(address), None) => write!(f, "at {:#05X}", address),
+            (None, Some(condition)) => write!(f, "if {}", condition),
+            (None, None) => write!(f, "always"),
+        }
+    }
+}
+
+/// Which memory accesses a watchpoint fires on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    /// Reads and writes.
+    Access,
+    Execute,
+}
+
+impl WatchKind {
+    fn matches(self, kind: AccessKind) -> bool {
+        match self {
+            Self::Read => kind == AccessKind::Read,
+            Self::Write => kind == AccessKind::Write,
+            Self::Access => kind != AccessKind::Execute,
+            Self::Execute => kind == AccessKind::Execute,
+        }
+    }
+}
+
+impl fmt::Display for WatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Read => write!(f, "read"),
+            Self::Write => write!(f, "write"),
+            Self::Access => write!(f, "access"),
+            Self::Execute => write!(f, "exec"),
+        }
+    }
+}
+
+impl FromStr for WatchKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "r" | "read" => Ok(Self::Read),
+            "w" | "write" => Ok(Self::Write),
+            "rw" | "access" => Ok(Self::Access),
+            "x" | "exec" => Ok(Self::Execute),
+            _ => Err(format!("expected read, write, access or exec, got `{}`", s)),
+        }
+    }
+}
+
+/// Pauses execution after an instruction accesses memory from `start` to
+/// `end` inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub kind: WatchKind,
+    pub start: u16,
+    pub end: u16,
+}
+
+impl Watchpoint {
+    fn matches(&self, access: &MemoryAccess) -> bool {
+        self.kind.matches(access.kind) && (self.start..=self.end).contains(&access.address)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:#05X}", self.kind, self.start)?;
+        if self.end != self.start {
+            write!(f, "-{:#05X}", self.end)?;
+        }
+        Ok(())
+    }
+}
+
+/// A memory access that fired a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub id: usize,
+    /// The address of the instruction that made the access.
+    pub instruction: u16,
+    pub access: MemoryAccess,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let MemoryAccess {
+            kind,
+            address,
+            old,
+            new,
+        } = self.access;
+        write!(f, "hit watchpoint {}: {:#05X} ", self.id, address)?;
+        match kind {
+            AccessKind::Read => write!(f, "read as {:#04X}", old)?,
+            AccessKind::Write => write!(f, "written, {:#04X} -> {:#04X}", old, new)?,
+            AccessKind::Execute => write!(f, "executed")?,
+        }
+        write!(f, " by the instruction at {:#05X}", self.instruction)
+    }
+}
+
+/// A line typed at the debugger prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Continue,
+    Pause,
+    Step(u32),
+    Frame,
+    Break(Breakpoint),
+    Watch(Watchpoint),
+    Delete(usize),
+    List,
+    Registers,
+    Help,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, args) = match s.find(char::is_whitespace) {
+            Some(i) => (&s[..i], s[i..].trim()),
+            None => (s, ""),
+        };
+        let no_args = |command| {
+            if args.is_empty() {
+                Ok(command)
+            } else {
+                Err(format!("`{}` takes no arguments", name))
+            }
+        };
+        match name {
+            "c" | "continue" => no_args(Self::Continue),
+            "p" | "pause" => no_args(Self::Pause),
+            "s" | "step" if args.is_empty() => Ok(Self::Step(1)),
+            "s" | "step" => args
+                .parse()
+                .map(Self::Step)
+                .map_err(|_| format!("expected an instruction count, got `{}`", args)),
+            "f" | "frame" => no_args(Self::Frame),
+            "b" | "break" => parse_breakpoint(args).map(Self::Break),
+            "w" | "watch" => parse_watchpoint(args).map(Self::Watch),
+            "d" | "delete" => args
+                .parse()
+                .map(Self::Delete)
+                .map_err(|_| format!("expected an id, got `{}`", args)),
+            "l" | "list" => no_args(Self::List),
+            "r" | "regs" => no_args(Self::Registers),
+            "h" | "help" => no_args(Self::Help),
+            _ => Err(format!("unknown command `{}`, try `help`", name)),
+        }
+    }
+}
+
+fn parse_breakpoint(s: &str) -> Result<Breakpoint, String> {
+    let (address, condition) = match s.strip_prefix("if") {
+        Some(condition) => ("", condition),
+        None => match s.find(" if ") {
+            Some(i) => (&s[..i], &s[i + 4..]),
+            None => (s, ""),
+        },
+    };
+    let address = match address.trim() {
+        "" => None,
+        address => Some(parse_number(address)?),
+    };
+    let condition = match condition.trim() {
+        "" => None,
+        condition => Some(condition.parse()?),
+    };
+    if address.is_none() && condition.is_none() {
+        return Err("expected an address or a condition".to_string());
+    }
+    Ok(Breakpoint { address, condition })
+}
+
+fn parse_watchpoint(s: &str) -> Result<Watchpoint, String> {
+    let mut args = s.split_whitespace();
+    let kind = args
+        .next()
+        .ok_or("expected read, write, access or exec")?
+        .parse()?;
+    let start = parse_number(args.next().ok_or("expected an address")?)?;
+    let end = args.next().map_or(Ok(start), parse_number)?;
+    if args.next().is_some() {
+        return Err("too many arguments".to_string());
+    }
+    if end < start {
+        return Err("the end of the range comes before its start".to_string());
+    }
+    Ok(Watchpoint { kind, start, end })
+}
+
+/// Parses a decimal number, or a hex one prefixed with `0x` or `$`.
+fn parse_number(s: &str) -> Result<u16, String> {
+    let hex = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .or_else(|| s.strip_prefix('$'));
+    match hex {
+        Some(digits) => u16::from_str_radix(digits, 16),
+        None => s.parse(),
+    }
+    .map_err(|_| format!("expected a number, got `{}`", s))
+}
+
+/// Why a running program was paused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// The breakpoint with this id was hit.
+    Breakpoint(usize),
+    Watchpoint(WatchHit),
+    /// A frame step reached the next timer tick.
+    FrameEnd,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Breakpoint(id) => write!(f, "hit breakpoint {}", id),
+            Self::Watchpoint(hit) => write!(f, "{}", hit),
+            Self::FrameEnd => write!(f, "end of frame"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunState {
+    Running,
+    Paused,
+    /// Running until the next tick.
+    FrameStep,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    breakpoint: Breakpoint,
+    /// Whether the breakpoint matched before the previous instruction. A
+    /// breakpoint only fires when it starts matching, so continuing from it
+    /// executes the instruction instead of stopping again.
+    matched: bool,
+}
+
+/// Controls execution of a `Chip8`: the frontend calls `cycle` and `tick`
+/// here instead of on the machine, and they do nothing while paused.
+#[derive(Debug)]
+pub struct Debugger {
+    state: RunState,
+    breakpoints: BTreeMap<usize, Entry>,
+    watchpoints: BTreeMap<usize, Watchpoint>,
+    /// Shared by breakpoints and watchpoints.
+    next_id: usize,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            state: RunState::Running,
+            breakpoints: BTreeMap::new(),
+            watchpoints: BTreeMap::new(),
+            next_id: 1,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state == RunState::Paused
+    }
+
+    pub fn pause(&mut self) {
+        self.state = RunState::Paused;
+    }
+
+    pub fn resume(&mut self) {
+        self.state = RunState::Running;
+    }
+
+    /// Runs until the next call to `tick`.
+    pub fn step_frame(&mut self) {
+        self.state = RunState::FrameStep;
+    }
+
+    /// Adds a breakpoint and returns its id.
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        let id = self.new_id();
+        self.breakpoints.insert(
+            id,
+            Entry {
+                breakpoint,
+                matched: false,
+            },
+        );
+        id
+    }
+
+    /// Returns whether a breakpoint with this id existed.
+    pub fn remove_breakpoint(&mut self, id: usize) -> bool {
+        self.breakpoints.remove(&id).is_some()
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (usize, &Breakpoint)> {
+        self.breakpoints
+            .iter()
+            .map(|(&id, entry)| (id, &entry.breakpoint))
+    }
+
+    /// Adds a watchpoint and returns its id.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        let id = self.new_id();
+        self.watchpoints.insert(id, watchpoint);
+        id
+    }
+
+    /// Returns whether a watchpoint with this id existed.
+    pub fn remove_watchpoint(&mut self, id: usize) -> bool {
+        self.watchpoints.remove(&id).is_some()
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = (usize, &Watchpoint)> {
+        self.watchpoints
+            .iter()
+            .map(|(&id, watchpoint)| (id, watchpoint))
+    }
+
+    /// Executes one instruction unless paused or a breakpoint is hit first.
+    /// Watchpoints are checked after the instruction has run.
+    ///
+    /// An error pauses the debugger.
+    pub fn cycle(&mut self, chip8: &mut Chip8) -> Result<Option<Stop>, Chip8Error> {
+        if self.is_paused() {
+            return Ok(None);
+        }
+        if let Some(id) = self.check_breakpoints(chip8.cpu()) {
+            self.pause();
+            return Ok(Some(Stop::Breakpoint(id)));
+        }
+        let instruction = chip8.cpu().instruction_pointer();
+        chip8.trace_memory(!self.watchpoints.is_empty());
+        chip8.cycle().inspect_err(|_| self.pause())?;
+        if let Some(hit) = self.check_watchpoints(instruction, chip8.cpu()) {
+            self.pause();
+            return Ok(Some(Stop::Watchpoint(hit)));
+        }
+        Ok(None)
+    }
+
+    /// Counts down the timers unless paused.
+    pub fn tick(&mut self, chip8: &mut Chip8) -> Option<Stop> {
+        match self.state {
+            RunState::Paused => None,
+            RunState::Running => {
+                chip8.tick();
+                None
+            }
+            RunState::FrameStep => {
+                chip8.tick();
+                self.pause();
+                Some(Stop::FrameEnd)
+            }
+        }
+    }
+
+    /// Pauses and executes one instruction, ignoring breakpoints.
+    pub fn step(&mut self, chip8: &mut Chip8) -> Result<(), Chip8Error> {
+        self.pause();
+        let result = chip8.cycle();
+        // Landing on a breakpoint should not make the next `cycle` stop
+        // before executing anything.
+        self.check_breakpoints(chip8.cpu());
+        result
+    }
+
+    /// Runs a command and returns the text to show the user.
+    pub fn execute(&mut self, command: Command, chip8: &mut Chip8) -> String {
+        match command {
+            Command::Continue => {
+                self.resume();
+                "continuing".to_string()
+            }
+            Command::Pause => {
+                self.pause();
+                format_state(chip8.cpu())
+            }
+            Command::Step(count) => {
+                for _ in 0..count {
+                    if let Err(error) = self.step(chip8) {
+                        return format!("chip8 halted: {}\n{}", error, format_state(chip8.cpu()));
+                    }
+                }
+                format_state(chip8.cpu())
+            }
+            Command::Frame => {
+                self.step_frame();
+                "running to the end of the frame".to_string()
+            }
+            Command::Break(breakpoint) => {
+                let id = self.add_breakpoint(breakpoint);
+                format!("breakpoint {} {}", id, breakpoint)
+            }
+            Command::Watch(watchpoint) => {
+                let id = self.add_watchpoint(watchpoint);
+                format!("watchpoint {} {}", id, watchpoint)
+            }
+            Command::Delete(id) => {
+                if self.remove_breakpoint(id) {
+                    format!("deleted breakpoint {}", id)
+                } else if self.remove_watchpoint(id) {
+                    format!("deleted watchpoint {}", id)
+                } else {
+                    format!("no breakpoint or watchpoint {}", id)
+                }
+            }
+            Command::List => {
+                let mut lines: Vec<_> =
+                    self.breakpoints()
+                        .map(|(id, breakpoint)| (id, format!("{:>3}  break {}", id, breakpoint)))
+                        .chain(self.watchpoints().map(|(id, watchpoint)| {
+                            (id, format!("{:>3}  watch {}", id, watchpoint))
+                        }))
+                        .collect();
+                lines.sort();
+                if lines.is_empty() {
+                    "no breakpoints or watchpoints".to_string()
+                } else {
+                    let lines: Vec<_> = lines.into_iter().map(|(_, line)| line).collect();
+                    lines.join("\n")
+                }
+            }
+            Command::Registers => format_state(chip8.cpu()),
+            Command::Help => HELP.to_string(),
+        }
+    }
+
+    fn new_id(&mut self) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Returns the first access made by the last instruction that a
+    /// watchpoint covers.
+    fn check_watchpoints(&self, instruction: u16, cpu: &CPU) -> Option<WatchHit> {
+        cpu.memory_accesses().iter().find_map(|access| {
+            self.watchpoints
+                .iter()
+                .find(|(_, watchpoint)| watchpoint.matches(access))
+                .map(|(&id, _)| WatchHit {
+                    id,
+                    instruction,
+                    access: *access,
+                })
+        })
+    }
+
+    /// Updates which breakpoints match and returns the first one that just
+    /// started to.
+    fn check_breakpoints(&mut self, cpu: &CPU) -> Option<usize> {
+        let mut hit = None;
+        for (&id, entry) in self.breakpoints.iter_mut() {
+            let matches = entry.breakpoint.matches(cpu);
+            if matches && !entry.matched && hit.is_none() {
+                hit = Some(id);
+            }
+            entry.matched = matches;
+        }
+        hit
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Describes the registers, timers, stack and next instruction.
+pub fn format_state(cpu: &CPU) -> String {
+    let registers = cpu.registers();
+    let row = |range: std::ops::Range<usize>| {
+        range
+            .map(|x| format!("V{:X} {:02X}", x, registers[x]))
+            .collect::<Vec<_>>()
+            .join("  ")
+    };
+    let stack = if cpu.stack().is_empty() {
+        "empty".to_string()
+    } else {
+        cpu.stack()
+            .iter()
+            .map(|address| format!("{:03X}", address))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let pc = cpu.instruction_pointer();
+    let next = match cpu.current_opcode() {
+        Some(opcode) => format!(
+            "{:04X}  {}",
+            opcode,
+            disassembler::disassemble_at(cpu.memory(), pc as usize, Syntax::Classic)
+        ),
+        None => "(outside memory)".to_string(),
+    };
+    format!(
+        "PC {:03X}  I {:03X}  DT {:02X}  ST {:02X}\n{}\n{}\nstack: {}\n{:03X}: {}",
+        pc,
+        cpu.address_register(),
+        cpu.delay_timer(),
+        cpu.sound_timer(),
+        row(0..8),
+        row(8..16),
+        stack,
+        pc,
+        next,
+    )
+}
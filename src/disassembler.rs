@@ -0,0 +1,288 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryInto;
+
+use crate::cpu::{Instruction, PROGRAM_START};
+
+/// The mnemonics used in listings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    /// Octo statements such as `v0 := 0x05` and `if v1 == v2 then`.
+    Octo,
+    /// Cowgod-style mnemonics such as `LD V0, 0x05` and `SNE V1, V2`.
+    Classic,
+}
+
+/// Lists a program loaded at 0x200, one instruction per line with its
+/// address and raw bytes. Targets of jumps and calls get labels; words that
+/// are not valid instructions are shown as data.
+pub fn disassemble(program: &[u8], syntax: Syntax) -> String {
+    let mut items = Vec::new();
+    let mut offset = 0;
+    while offset < program.len() {
+        let item = decode(program, offset);
+        let len = item.len();
+        items.push((offset, item));
+        offset += len;
+    }
+
+    // Only addresses where a listed instruction starts can be labelled
+    let starts: BTreeSet<usize> = items
+        .iter()
+        .map(|&(offset, _)| PROGRAM_START + offset)
+        .collect();
+    let mut labels = BTreeMap::new();
+    for (_, item) in &items {
+        let (address, prefix) = match item {
+            Item::Instruction(Instruction::Subroutine { address }, _) => (*address, "sub"),
+            Item::Instruction(Instruction::Jump { address }, _)
+            | Item::Instruction(Instruction::JumpOffset { address }, _) => (*address, "label"),
+            _ => continue,
+        };
+        // Subroutine names win over plain labels for the same address
+        if starts.contains(&(address as usize))
+            && (prefix == "sub" || !labels.contains_key(&address))
+        {
+            labels.insert(address, format!("{}_{:03X}", prefix, address));
+        }
+    }
+
+    let mut listing = String::new();
+    for (offset, item) in &items {
+        let address = PROGRAM_START + offset;
+        if let Some(label) = labels.get(&(address as u16)) {
+            match syntax {
+                Syntax::Octo => listing.push_str(&format!(": {}\n", label)),
+                Syntax::Classic => listing.push_str(&format!("{}:\n", label)),
+            }
+        }
+        let bytes = &program[*offset..offset + item.len()];
+        listing.push_str(&format!(
+            "{:03X}  {:<9}  {}\n",
+            address,
+            hex_words(bytes),
+            item.format(bytes, syntax, &labels)
+        ));
+    }
+    listing
+}
+
+/// Describes the instruction at `address` in `memory` without labels.
+pub fn disassemble_at(memory: &[u8], address: usize, syntax: Syntax) -> String {
+    if address >= memory.len() {
+        return "(outside memory)".to_string();
+    }
+    let item = decode(memory, address);
+    let bytes = &memory[address..address + item.len()];
+    item.format(bytes, syntax, &BTreeMap::new())
+}
+
+enum Item {
+    /// An instruction and, for F000 NNNN, its second word.
+    Instruction(Instruction, Option<u16>),
+    Data(usize),
+}
+
+impl Item {
+    fn len(&self) -> usize {
+        match self {
+            Self::Instruction(_, Some(_)) => 4,
+            Self::Instruction(_, None) => 2,
+            Self::Data(len) => *len,
+        }
+    }
+
+    fn format(&self, bytes: &[u8], syntax: Syntax, labels: &BTreeMap<u16, String>) -> String {
+        match self {
+            Self::Instruction(instruction, long) => {
+                let formatter = Formatter { syntax, labels };
+                formatter.instruction(*instruction, long.unwrap_or_default())
+            }
+            Self::Data(_) => {
+                let values: Vec<_> = bytes.iter().map(|byte| format!("{:#04X}", byte)).collect();
+                match syntax {
+                    Syntax::Octo => values.join(" "),
+                    Syntax::Classic => format!(":byte {}", values.join(" ")),
+                }
+            }
+        }
+    }
+}
+
+fn decode(memory: &[u8], address: usize) -> Item {
+    let word = |address: usize| {
+        memory
+            .get(address..address + 2)
+            .map(|bytes| u16::from_be_bytes(bytes.try_into().unwrap()))
+    };
+    let opcode = match word(address) {
+        Some(opcode) => opcode,
+        None => return Item::Data(memory.len() - address),
+    };
+    match Instruction::from_opcode(opcode) {
+        Some(Instruction::SetILong) => match word(address + 2) {
+            Some(long) => Item::Instruction(Instruction::SetILong, Some(long)),
+            None => Item::Data(memory.len() - address),
+        },
+        Some(instruction) => Item::Instruction(instruction, None),
+        None => Item::Data(2),
+    }
+}
+
+fn hex_words(bytes: &[u8]) -> String {
+    bytes
+        .chunks(2)
+        .map(|word| word.iter().map(|byte| format!("{:02X}", byte)).collect())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+struct Formatter<'a> {
+    syntax: Syntax,
+    labels: &'a BTreeMap<u16, String>,
+}
+
+impl Formatter<'_> {
+    fn v(&self, register: u8) -> String {
+        match self.syntax {
+            Syntax::Octo => format!("v{:x}", register),
+            Syntax::Classic => format!("V{:X}", register),
+        }
+    }
+
+    fn address(&self, address: u16) -> String {
+        self.labels
+            .get(&address)
+            .cloned()
+            .unwrap_or_else(|| format!("{:#05X}", address))
+    }
+
+    fn instruction(&self, instruction: Instruction, long: u16) -> String {
+        match self.syntax {
+            Syntax::Octo => self.octo(instruction, long),
+            Syntax::Classic => self.classic(instruction, long),
+        }
+    }
+
+    fn octo(&self, instruction: Instruction, long: u16) -> String {
+        use Instruction::*;
+        let v = |register| self.v(register);
+        match instruction {
+            // Octo has no statement for machine code calls
+            RCA1802 { address } => format!("{:#04X} {:#04X}", address >> 8, address & 0xFF),
+            ScrollDown { rows } => format!("scroll-down {}", rows),
+            ScrollUp { rows } => format!("scroll-up {}", rows),
+            ClearScreen => "clear".to_string(),
+            Return => "return".to_string(),
+            ScrollRight => "scroll-right".to_string(),
+            ScrollLeft => "scroll-left".to_string(),
+            Exit => "exit".to_string(),
+            LowRes => "lores".to_string(),
+            HighRes => "hires".to_string(),
+            Jump { address } => format!("jump {}", self.address(address)),
+            Subroutine { address } => format!(":call {}", self.address(address)),
+            // Octo conditions say when the next instruction runs, the
+            // opposite of when it is skipped
+            IfEqualConst { register, value } => {
+                format!("if {} != {:#04X} then", v(register), value)
+            }
+            IfNotEqualConst { register, value } => {
+                format!("if {} == {:#04X} then", v(register), value)
+            }
+            IfEqualRegister { a, b } => format!("if {} != {} then", v(a), v(b)),
+            SaveRange { a, b } => format!("save {} - {}", v(a), v(b)),
+            LoadRange { a, b } => format!("load {} - {}", v(a), v(b)),
+            SetConst { register, value } => format!("{} := {:#04X}", v(register), value),
+            AddConst { register, value } => format!("{} += {:#04X}", v(register), value),
+            SetRegister { dest, src } => format!("{} := {}", v(dest), v(src)),
+            Or { a, b } => format!("{} |= {}", v(a), v(b)),
+            And { a, b } => format!("{} &= {}", v(a), v(b)),
+            Xor { a, b } => format!("{} ^= {}", v(a), v(b)),
+            Add { a, b } => format!("{} += {}", v(a), v(b)),
+            Sub { a, b } => format!("{} -= {}", v(a), v(b)),
+            ShiftRight { a, b } => format!("{} >>= {}", v(a), v(b)),
+            NegSub { a, b } => format!("{} =- {}", v(a), v(b)),
+            ShiftLeft { a, b } => format!("{} <<= {}", v(a), v(b)),
+            IfNotEqualRegister { a, b } => format!("if {} == {} then", v(a), v(b)),
+            SetI { address } => format!("i := {}", self.address(address)),
+            JumpOffset { address } => format!("jump0 {}", self.address(address)),
+            Rand { register, value } => format!("{} := random {:#04X}", v(register), value),
+            DrawSprite { x, y, height } => format!("sprite {} {} {}", v(x), v(y), height),
+            IfPressed { register } => format!("if {} -key then", v(register)),
+            IfNotPressed { register } => format!("if {} key then", v(register)),
+            SetILong => format!("i := long {:#06X}", long),
+            SelectPlanes { planes } => format!("plane {}", planes),
+            LoadAudio => "audio".to_string(),
+            GetTimer { register } => format!("{} := delay", v(register)),
+            AwaitInput { register } => format!("{} := key", v(register)),
+            SetTimer { register } => format!("delay := {}", v(register)),
+            SetSound { register } => format!("buzzer := {}", v(register)),
+            AddToI { register } => format!("i += {}", v(register)),
+            SetIToFontChar { register } => format!("i := hex {}", v(register)),
+            SetIToBigFontChar { register } => format!("i := bighex {}", v(register)),
+            BinaryCodedDecimal { register } => format!("bcd {}", v(register)),
+            SetPitch { register } => format!("pitch := {}", v(register)),
+            RegisterDump { register } => format!("save {}", v(register)),
+            RegisterLoad { register } => format!("load {}", v(register)),
+            SaveFlags { register } => format!("saveflags {}", v(register)),
+            LoadFlags { register } => format!("loadflags {}", v(register)),
+        }
+    }
+
+    fn classic(&self, instruction: Instruction, long: u16) -> String {
+        use Instruction::*;
+        let v = |register| self.v(register);
+        match instruction {
+            RCA1802 { address } => format!("SYS {:#05X}", address),
+            ScrollDown { rows } => format!("SCD {}", rows),
+            ScrollUp { rows } => format!("SCU {}", rows),
+            ClearScreen => "CLS".to_string(),
+            Return => "RET".to_string(),
+            ScrollRight => "SCR".to_string(),
+            ScrollLeft => "SCL".to_string(),
+            Exit => "EXIT".to_string(),
+            LowRes => "LOW".to_string(),
+            HighRes => "HIGH".to_string(),
+            Jump { address } => format!("JP {}", self.address(address)),
+            Subroutine { address } => format!("CALL {}", self.address(address)),
+            IfEqualConst { register, value } => format!("SE {}, {:#04X}", v(register), value),
+            IfNotEqualConst { register, value } => format!("SNE {}, {:#04X}", v(register), value),
+            IfEqualRegister { a, b } => format!("SE {}, {}", v(a), v(b)),
+            SaveRange { a, b } => format!("SAVE {}, {}", v(a), v(b)),
+            LoadRange { a, b } => format!("LOAD {}, {}", v(a), v(b)),
+            SetConst { register, value } => format!("LD {}, {:#04X}", v(register), value),
+            AddConst { register, value } => format!("ADD {}, {:#04X}", v(register), value),
+            SetRegister { dest, src } => format!("LD {}, {}", v(dest), v(src)),
+            Or { a, b } => format!("OR {}, {}", v(a), v(b)),
+            And { a, b } => format!("AND {}, {}", v(a), v(b)),
+            Xor { a, b } => format!("XOR {}, {}", v(a), v(b)),
+            Add { a, b } => format!("ADD {}, {}", v(a), v(b)),
+            Sub { a, b } => format!("SUB {}, {}", v(a), v(b)),
+            ShiftRight { a, b } => format!("SHR {}, {}", v(a), v(b)),
+            NegSub { a, b } => format!("SUBN {}, {}", v(a), v(b)),
+            ShiftLeft { a, b } => format!("SHL {}, {}", v(a), v(b)),
+            IfNotEqualRegister { a, b } => format!("SNE {}, {}", v(a), v(b)),
+            SetI { address } => format!("LD I, {}", self.address(address)),
+            JumpOffset { address } => format!("JP V0, {}", self.address(address)),
+            Rand { register, value } => format!("RND {}, {:#04X}", v(register), value),
+            DrawSprite { x, y, height } => format!("DRW {}, {}, {}", v(x), v(y), height),
+            IfPressed { register } => format!("SKP {}", v(register)),
+            IfNotPressed { register } => format!("SKNP {}", v(register)),
+            SetILong => format!("LD I, LONG {:#06X}", long),
+            SelectPlanes { planes } => format!("PLANE {}", planes),
+            LoadAudio => "AUDIO".to_string(),
+            GetTimer { register } => format!("LD {}, DT", v(register)),
+            AwaitInput { register } => format!("LD {}, K", v(register)),
+            SetTimer { register } => format!("LD DT, {}", v(register)),
+            SetSound { register } => format!("LD ST, {}", v(register)),
+            AddToI { register } => format!("ADD I, {}", v(register)),
+            SetIToFontChar { register } => format!("LD F, {}", v(register)),
+            SetIToBigFontChar { register } => format!("LD HF, {}", v(register)),
+            BinaryCodedDecimal { register } => format!("LD B, {}", v(register)),
+            SetPitch { register } => format!("PITCH {}", v(register)),
+            RegisterDump { register } => format!("LD [I], {}", v(register)),
+            RegisterLoad { register } => format!("LD {}, [I]", v(register)),
+            SaveFlags { register } => format!("LD R, {}", v(register)),
+            LoadFlags { register } => format!("LD {}, R", v(register)),
+        }
+    }
+}
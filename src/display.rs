@@ -1,46 +1,165 @@
-const WIDTH: usize = 64;
-const HEIGHT: usize = 32;
+use crate::state::{SaveStateError, StateReader, StateWriter};
 
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+pub const VIP_HIRES_WIDTH: usize = 64;
+pub const VIP_HIRES_HEIGHT: usize = 64;
+
+/// A framebuffer of two bitplanes.
+///
+/// Each pixel is a colour index from 0 to 3 whose bits are the pixel's value
+/// in plane 1 and plane 2. Clearing and scrolling only affect the planes
+/// selected with `select_planes`; plain CHIP-8 programs only ever use plane 1.
+///
+/// Rows are stored bottom to top so the buffer can be uploaded as a texture
+/// as is.
 #[derive(Debug, Clone)]
-pub struct Display(Vec<bool>);
+pub struct Display {
+    width: usize,
+    height: usize,
+    planes: u8,
+    pixels: Vec<u8>,
+}
 
 impl Display {
     pub fn new() -> Self {
-        Self(vec![false; WIDTH * HEIGHT])
+        Self::with_dimensions(WIDTH, HEIGHT)
     }
 
-    pub fn get_pixel(&self, x: u8, y: u8) -> bool {
-        let x = x as usize;
-        let y = y as usize;
-        if x >= WIDTH || y >= HEIGHT {
-            panic!("Pixel coordinate out of range: {:?}", (x, y));
+    pub fn with_dimensions(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            planes: 1,
+            pixels: vec![0; width * height],
         }
-        let i = (HEIGHT - 1 - y) * WIDTH + x;
-        self.0[i]
     }
 
-    pub fn set_pixel(&mut self, x: u8, y: u8, value: bool) {
-        let x = x as usize;
-        let y = y as usize;
-        if x >= WIDTH || y >= HEIGHT {
-            panic!("Pixel coordinate out of range: {:?}", (x, y));
+    /// Pixels outside the display are always 0.
+    pub fn get_pixel(&self, x: u8, y: u8) -> u8 {
+        self.index(x as usize, y as usize)
+            .map_or(0, |i| self.pixels[i])
+    }
+
+    /// Setting a pixel outside the display does nothing.
+    pub fn set_pixel(&mut self, x: u8, y: u8, value: u8) {
+        if let Some(i) = self.index(x as usize, y as usize) {
+            self.pixels[i] = value & 0b11;
         }
-        let i = (HEIGHT - 1 - y) * WIDTH + x;
-        let pixel = &mut self.0[i];
-        *pixel = value;
+    }
+
+    /// The bitmask of planes affected by clearing and scrolling.
+    pub fn selected_planes(&self) -> u8 {
+        self.planes
+    }
+
+    pub fn select_planes(&mut self, planes: u8) {
+        self.planes = planes & 0b11;
     }
 
     pub fn clear(&mut self) {
-        for v in self.0.iter_mut() {
-            *v = false;
+        let keep = !self.planes;
+        for v in self.pixels.iter_mut() {
+            *v &= keep;
         }
     }
 
+    /// Changes the resolution, clearing every plane.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        *self = Self {
+            planes: self.planes,
+            ..Self::with_dimensions(width, height)
+        };
+    }
+
+    pub fn scroll_up(&mut self, rows: usize) {
+        self.scroll(0, -(rows as isize));
+    }
+
+    pub fn scroll_down(&mut self, rows: usize) {
+        self.scroll(0, rows as isize);
+    }
+
+    pub fn scroll_left(&mut self, columns: usize) {
+        self.scroll(-(columns as isize), 0);
+    }
+
+    pub fn scroll_right(&mut self, columns: usize) {
+        self.scroll(columns as isize, 0);
+    }
+
     pub fn dimensions(&self) -> (u8, u8) {
-        (WIDTH as u8, HEIGHT as u8)
+        (self.width as u8, self.height as u8)
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.pixels
+    }
+
+    pub(crate) fn write_state(&self, writer: &mut StateWriter) {
+        writer.u8(self.width as u8);
+        writer.u8(self.height as u8);
+        writer.u8(self.planes);
+        writer.bytes(&self.pixels);
+    }
+
+    pub(crate) fn read_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        let (width, height) = (reader.u8()? as usize, reader.u8()? as usize);
+        let resolutions = [
+            (WIDTH, HEIGHT),
+            (VIP_HIRES_WIDTH, VIP_HIRES_HEIGHT),
+            (HIRES_WIDTH, HIRES_HEIGHT),
+        ];
+        if !resolutions.contains(&(width, height)) {
+            return Err(SaveStateError::Corrupt);
+        }
+        let planes = reader.u8()?;
+        let pixels = reader.bytes(width * height)?;
+        if planes > 0b11 || pixels.iter().any(|&v| v > 0b11) {
+            return Err(SaveStateError::Corrupt);
+        }
+        *self = Self {
+            width,
+            height,
+            planes,
+            pixels: pixels.to_vec(),
+        };
+        Ok(())
+    }
+
+    /// Moves the selected planes by (dx, dy), filling the uncovered area with
+    /// off pixels.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let planes = self.planes;
+        let mut scrolled: Vec<u8> = self.pixels.iter().map(|&v| v & !planes).collect();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let src_x = x as isize - dx;
+                let src_y = y as isize - dy;
+                if src_x < 0 || src_y < 0 {
+                    continue;
+                }
+                if let Some(src) = self.index(src_x as usize, src_y as usize) {
+                    let dest = self.index(x, y).unwrap();
+                    scrolled[dest] |= self.pixels[src] & planes;
+                }
+            }
+        }
+        self.pixels = scrolled;
     }
 
-    pub fn into_inner(self) -> Vec<bool> {
-        self.0
+    fn index(&self, x: usize, y: usize) -> Option<usize> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some((self.height - 1 - y) * self.width + x)
+    }
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
     }
 }
@@ -0,0 +1,40 @@
+use std::error::Error;
+use std::fmt;
+
+/// Why a program could not be loaded, or why the machine halted.
+///
+/// Runtime errors carry the address of the instruction that caused them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chip8Error {
+    ProgramTooLarge { size: usize, max: usize },
+    InvalidOpcode { address: u16, opcode: u16 },
+    StackOverflow { address: u16 },
+    StackUnderflow { address: u16 },
+    MemoryOutOfBounds { address: u16, location: usize },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::ProgramTooLarge { size, max } => write!(
+                f,
+                "program is {} bytes but at most {} bytes fit in memory",
+                size, max
+            ),
+            Self::InvalidOpcode { address, opcode } => {
+                write!(f, "invalid opcode {:04X} at {:03X}", opcode, address)
+            }
+            Self::StackOverflow { address } => write!(f, "stack overflow at {:03X}", address),
+            Self::StackUnderflow { address } => {
+                write!(f, "return with an empty stack at {:03X}", address)
+            }
+            Self::MemoryOutOfBounds { address, location } => write!(
+                f,
+                "memory access out of bounds at {:#X} by instruction at {:03X}",
+                location, address
+            ),
+        }
+    }
+}
+
+impl Error for Chip8Error {}
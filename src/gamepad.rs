@@ -0,0 +1,94 @@
+use anyhow::{anyhow, Result};
+use gilrs::{Axis, Button, EventType, GamepadId, Gilrs};
+
+/// How far a stick must be pushed to count as a d-pad press.
+const STICK_THRESHOLD: f32 = 0.5;
+
+/// Reads all connected gamepads as if they were one. The left stick acts as
+/// the d-pad.
+pub struct Gamepads {
+    gilrs: Gilrs,
+    /// Buttons held on each gamepad, and whether the stick holds them.
+    held: Vec<(GamepadId, Button, bool)>,
+}
+
+impl Gamepads {
+    pub fn new() -> Result<Self> {
+        let gilrs = Gilrs::new().map_err(|error| anyhow!("{}", error))?;
+        Ok(Self {
+            gilrs,
+            held: Vec::new(),
+        })
+    }
+
+    /// The buttons pressed or released since the last call. A button is
+    /// only released once no gamepad holds it.
+    pub fn events(&mut self) -> Vec<(Button, bool)> {
+        let mut events = Vec::new();
+        while let Some(event) = self.gilrs.next_event() {
+            let id = event.id;
+            match event.event {
+                EventType::ButtonPressed(button, _) => {
+                    self.set(id, button, false, true, &mut events)
+                }
+                EventType::ButtonReleased(button, _) => {
+                    self.set(id, button, false, false, &mut events)
+                }
+                EventType::AxisChanged(Axis::LeftStickX, value, _) => self.stick(
+                    id,
+                    [Button::DPadLeft, Button::DPadRight],
+                    value,
+                    &mut events,
+                ),
+                EventType::AxisChanged(Axis::LeftStickY, value, _) => {
+                    self.stick(id, [Button::DPadDown, Button::DPadUp], value, &mut events)
+                }
+                EventType::Disconnected => {
+                    let held: Vec<_> = self.held.iter().copied().filter(|h| h.0 == id).collect();
+                    for (_, button, stick) in held {
+                        self.set(id, button, stick, false, &mut events);
+                    }
+                }
+                _ => {}
+            }
+        }
+        events
+    }
+
+    /// Holds the first button while the stick axis is pushed to the
+    /// negative side and the second while it is pushed to the positive one.
+    fn stick(
+        &mut self,
+        id: GamepadId,
+        [negative, positive]: [Button; 2],
+        value: f32,
+        events: &mut Vec<(Button, bool)>,
+    ) {
+        self.set(id, negative, true, value < -STICK_THRESHOLD, events);
+        self.set(id, positive, true, value > STICK_THRESHOLD, events);
+    }
+
+    fn set(
+        &mut self,
+        id: GamepadId,
+        button: Button,
+        stick: bool,
+        pressed: bool,
+        events: &mut Vec<(Button, bool)>,
+    ) {
+        let was_held = self.is_held(button);
+        let entry = (id, button, stick);
+        if !pressed {
+            self.held.retain(|&held| held != entry);
+        } else if !self.held.contains(&entry) {
+            self.held.push(entry);
+        }
+        if self.is_held(button) != was_held {
+            events.push((button, !was_held));
+        }
+    }
+
+    fn is_held(&self, button: Button) -> bool {
+        self.held.iter().any(|&(_, held, _)| held == button)
+    }
+}
@@ -0,0 +1,478 @@
+//! A stub for the GDB remote serial protocol, so that debuggers which speak
+//! it can attach to a machine over TCP.
+//!
+//! The registers are V0-VF, I, PC, SP, DT and ST, numbered from 0 in that
+//! order. I and PC are 16-bit little-endian values and the others are bytes;
+//! SP is the number of return addresses on the stack and can only be
+//! written with its current value. Clients that read `target.xml` learn
+//! this from it.
+
+use std::collections::BTreeMap;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::str;
+use std::thread;
+use std::time::Duration;
+
+use crate::chip8::Chip8;
+use crate::cpu::CPU;
+use crate::debugger::{Breakpoint, Debugger};
+use crate::error::Chip8Error;
+
+const REGISTER_COUNT: usize = 21;
+const I: usize = 16;
+const PC: usize = 17;
+const SP: usize = 18;
+const DT: usize = 19;
+const ST: usize = 20;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+/// Whether a client is still attached after `GdbStub::poll`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Attached,
+    /// The client detached or closed the connection. Its breakpoints are
+    /// removed and the machine is left running.
+    Detached,
+    /// The client asked for the program to be killed.
+    Killed,
+}
+
+/// Something the client sent.
+enum Incoming {
+    Packet(String),
+    /// A Ctrl-C byte sent while the machine runs.
+    Interrupt,
+}
+
+/// Serves one client connection. The frontend calls `poll` regularly and
+/// runs the machine through the same [`Debugger`], which the stub pauses
+/// and resumes for the client. Breakpoints set with Z0 and Z1 become
+/// debugger breakpoints.
+pub struct GdbStub {
+    stream: TcpStream,
+    input: Vec<u8>,
+    no_ack: bool,
+    /// The debugger ids of the client's breakpoints, by address.
+    breakpoints: BTreeMap<u16, usize>,
+    /// Whether the client continued the machine and is waiting for it to
+    /// stop.
+    running: bool,
+    interrupted: bool,
+    status: Status,
+}
+
+impl GdbStub {
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            input: Vec::new(),
+            no_ack: false,
+            breakpoints: BTreeMap::new(),
+            running: false,
+            interrupted: false,
+            status: Status::Attached,
+        })
+    }
+
+    /// Handles everything the client has sent since the last call without
+    /// waiting for more, and tells the client when the machine has stopped
+    /// after it was continued.
+    pub fn poll(&mut self, debugger: &mut Debugger, chip8: &mut Chip8) -> io::Result<Status> {
+        if self.status != Status::Attached {
+            return Ok(self.status);
+        }
+        self.receive()?;
+        while let Some(incoming) = self.next_incoming()? {
+            match incoming {
+                Incoming::Packet(packet) => self.handle(&packet, debugger, chip8)?,
+                Incoming::Interrupt => {
+                    self.interrupted = self.running;
+                    debugger.pause();
+                }
+            }
+            if self.status != Status::Attached {
+                break;
+            }
+        }
+        if self.status == Status::Detached {
+            for id in self.breakpoints.values() {
+                debugger.remove_breakpoint(*id);
+            }
+            self.breakpoints.clear();
+            debugger.resume();
+        } else if self.running && (debugger.is_paused() || chip8.is_halted()) {
+            self.running = false;
+            let reply = if self.interrupted {
+                format!("S{:02x}", SIGINT)
+            } else {
+                stop_reply(chip8)
+            };
+            self.interrupted = false;
+            self.send(&reply)?;
+        }
+        Ok(self.status)
+    }
+
+    /// Reads whatever has arrived without blocking.
+    fn receive(&mut self) -> io::Result<()> {
+        self.stream.set_nonblocking(true)?;
+        let mut buffer = [0; 4096];
+        let result = loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => {
+                    self.status = Status::Detached;
+                    break Ok(());
+                }
+                Ok(n) => self.input.extend_from_slice(&buffer[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break Ok(()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => break Err(e),
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        result
+    }
+
+    /// Takes the next complete packet or interrupt from the input,
+    /// acknowledging packets unless the client turned that off. Acks from
+    /// the client are skipped: replies are never sent again.
+    fn next_incoming(&mut self) -> io::Result<Option<Incoming>> {
+        loop {
+            match self.input.first() {
+                None => return Ok(None),
+                Some(b'$') => {
+                    let end = match self.input.iter().position(|&b| b == b'#') {
+                        Some(end) if self.input.len() >= end + 3 => end,
+                        _ => return Ok(None),
+                    };
+                    let packet: Vec<u8> = self.input.drain(..end + 3).collect();
+                    let data = &packet[1..end];
+                    let valid = str::from_utf8(&packet[end + 1..])
+                        .ok()
+                        .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                        == Some(checksum(data));
+                    if !self.no_ack {
+                        self.stream.write_all(if valid { b"+" } else { b"-" })?;
+                    }
+                    if valid {
+                        let packet = String::from_utf8_lossy(data).into_owned();
+                        return Ok(Some(Incoming::Packet(packet)));
+                    }
+                }
+                Some(0x03) => {
+                    self.input.remove(0);
+                    return Ok(Some(Incoming::Interrupt));
+                }
+                Some(_) => {
+                    self.input.remove(0);
+                }
+            }
+        }
+    }
+
+    fn handle(
+        &mut self,
+        packet: &str,
+        debugger: &mut Debugger,
+        chip8: &mut Chip8,
+    ) -> io::Result<()> {
+        // A garbled packet may not start with a one-byte character
+        let kind = packet.get(..1).unwrap_or_default();
+        let args = packet.get(1..).unwrap_or_default();
+        let reply = match kind {
+            "?" => Some(stop_reply(chip8)),
+            "g" => Some(
+                (0..REGISTER_COUNT)
+                    .map(|n| encode_hex(&read_register(chip8.cpu(), n)))
+                    .collect(),
+            ),
+            "G" => Some(ok_or_error(write_registers(chip8.cpu_mut(), args))),
+            "p" => Some(
+                parse_hex(args)
+                    .filter(|&n| n < REGISTER_COUNT)
+                    .map_or_else(error, |n| encode_hex(&read_register(chip8.cpu(), n))),
+            ),
+            "P" => Some(ok_or_error(args.split_once('=').and_then(|(n, value)| {
+                let n = parse_hex(n).filter(|&n| n < REGISTER_COUNT)?;
+                write_register(chip8.cpu_mut(), n, &decode_hex(value)?)
+            }))),
+            "m" => Some(read_memory(chip8.cpu(), args).unwrap_or_else(error)),
+            "M" => Some(ok_or_error(write_memory(chip8.cpu_mut(), args))),
+            "Z" | "z" => Some(self.breakpoint(kind == "Z", args, debugger)),
+            "c" | "s" => {
+                if !args.is_empty() {
+                    let len = chip8.cpu().memory().len();
+                    match parse_hex(args).filter(|&address| address < len) {
+                        Some(address) => chip8.cpu_mut().set_instruction_pointer(address as u16),
+                        None => return self.send(&error()),
+                    }
+                }
+                if kind == "s" {
+                    let _ = debugger.step(chip8);
+                    Some(stop_reply(chip8))
+                } else if chip8.is_halted() {
+                    Some(stop_reply(chip8))
+                } else {
+                    debugger.resume();
+                    self.running = true;
+                    None
+                }
+            }
+            "k" => {
+                self.status = Status::Killed;
+                None
+            }
+            "D" => {
+                self.status = Status::Detached;
+                Some("OK".to_string())
+            }
+            "H" => Some("OK".to_string()),
+            _ => Some(self.query(packet)),
+        };
+        match reply {
+            Some(reply) => self.send(&reply),
+            None => Ok(()),
+        }
+    }
+
+    /// Replies to the general queries and settings this stub supports, and
+    /// with an empty packet to anything it does not.
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            "PacketSize=1000;QStartNoAckMode+;qXfer:features:read+".to_string()
+        } else if packet == "QStartNoAckMode" {
+            // This packet itself was still acknowledged.
+            self.no_ack = true;
+            "OK".to_string()
+        } else if packet == "qAttached" {
+            "1".to_string()
+        } else if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let description = target_description();
+            range
+                .split_once(',')
+                .and_then(|(offset, len)| Some((parse_hex(offset)?, parse_hex(len)?)))
+                .map_or_else(error, |(offset, len)| {
+                    let start = offset.min(description.len());
+                    let end = (start + len).min(description.len());
+                    let more = if end < description.len() { "m" } else { "l" };
+                    format!("{}{}", more, &description[start..end])
+                })
+        } else {
+            String::new()
+        }
+    }
+
+    /// Inserts or removes a breakpoint from `type,address,kind` arguments.
+    /// Software and hardware breakpoints are the same thing here.
+    fn breakpoint(&mut self, insert: bool, args: &str, debugger: &mut Debugger) -> String {
+        let mut args = args.split(',');
+        let kind = args.next();
+        if kind != Some("0") && kind != Some("1") {
+            return String::new();
+        }
+        let address = match args.next().and_then(parse_hex) {
+            Some(address) if address <= u16::MAX as usize => address as u16,
+            _ => return error(),
+        };
+        if insert {
+            self.breakpoints.entry(address).or_insert_with(|| {
+                debugger.add_breakpoint(Breakpoint {
+                    address: Some(address),
+                    condition: None,
+                })
+            });
+        } else if let Some(id) = self.breakpoints.remove(&address) {
+            debugger.remove_breakpoint(id);
+        }
+        "OK".to_string()
+    }
+
+    /// Sends a packet. Replies are plain ASCII without `$`, `#` or `}`, so
+    /// nothing needs escaping.
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        write!(self.stream, "${}#{:02x}", data, checksum(data.as_bytes()))
+    }
+}
+
+/// Serves one client on `stream` without a frontend. Whenever the client
+/// has continued the machine it runs as fast as it can, with a timer tick
+/// after every `cycles_per_tick` instructions. Returns once the client
+/// detaches or kills the program.
+pub fn serve(stream: TcpStream, chip8: &mut Chip8, cycles_per_tick: u32) -> io::Result<Status> {
+    let mut stub = GdbStub::new(stream)?;
+    let mut debugger = Debugger::new();
+    debugger.pause();
+    let mut cycles = 0;
+    loop {
+        let status = stub.poll(&mut debugger, chip8)?;
+        if status != Status::Attached {
+            return Ok(status);
+        }
+        if debugger.is_paused() || chip8.is_halted() {
+            thread::sleep(Duration::from_millis(1));
+            continue;
+        }
+        // Check on the client every so often while running.
+        for _ in 0..1000 {
+            let _ = debugger.cycle(chip8);
+            cycles += 1;
+            if cycles % cycles_per_tick.max(1) == 0 {
+                debugger.tick(chip8);
+            }
+            if debugger.is_paused() || chip8.is_halted() {
+                break;
+            }
+        }
+    }
+}
+
+/// Why the machine stopped, as a stop reply packet.
+fn stop_reply(chip8: &Chip8) -> String {
+    let signal = match chip8.error() {
+        Some(Chip8Error::InvalidOpcode { .. }) => SIGILL,
+        Some(_) => SIGSEGV,
+        None if chip8.has_exited() => return "W00".to_string(),
+        None => SIGTRAP,
+    };
+    format!("S{:02x}", signal)
+}
+
+fn target_description() -> String {
+    let mut registers: Vec<_> = (0..16)
+        .map(|x| format!("<reg name=\"v{:x}\" bitsize=\"8\" type=\"uint8\"/>", x))
+        .collect();
+    registers.extend(
+        [
+            "<reg name=\"i\" bitsize=\"16\" type=\"data_ptr\"/>",
+            "<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>",
+            "<reg name=\"sp\" bitsize=\"8\" type=\"uint8\"/>",
+            "<reg name=\"dt\" bitsize=\"8\" type=\"uint8\"/>",
+            "<reg name=\"st\" bitsize=\"8\" type=\"uint8\"/>",
+        ]
+        .iter()
+        .map(|reg| reg.to_string()),
+    );
+    format!(
+        "<?xml version=\"1.0\"?>\
+         <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\"><feature name=\"org.chip8.core\">{}</feature></target>",
+        registers.concat()
+    )
+}
+
+fn register_size(n: usize) -> usize {
+    match n {
+        I | PC => 2,
+        _ => 1,
+    }
+}
+
+fn read_register(cpu: &CPU, n: usize) -> Vec<u8> {
+    match n {
+        I => cpu.address_register().to_le_bytes().to_vec(),
+        PC => cpu.instruction_pointer().to_le_bytes().to_vec(),
+        SP => vec![cpu.stack().len() as u8],
+        DT => vec![cpu.delay_timer()],
+        ST => vec![cpu.sound_timer()],
+        x => vec![cpu.registers()[x]],
+    }
+}
+
+/// Returns `None` if `value` has the wrong size or is an SP other than the
+/// current one.
+fn write_register(cpu: &mut CPU, n: usize, value: &[u8]) -> Option<()> {
+    if value.len() != register_size(n) {
+        return None;
+    }
+    let word = || u16::from_le_bytes([value[0], value[1]]);
+    match n {
+        I => cpu.set_address_register(word()),
+        PC => cpu.set_instruction_pointer(word()),
+        SP if value[0] as usize == cpu.stack().len() => {}
+        SP => return None,
+        DT => cpu.set_delay_timer(value[0]),
+        ST => cpu.set_sound_timer(value[0]),
+        x => cpu.registers_mut()[x] = value[0],
+    }
+    Some(())
+}
+
+/// Writes every register from a G packet, or none if it is malformed.
+fn write_registers(cpu: &mut CPU, args: &str) -> Option<()> {
+    let values = decode_hex(args)?;
+    let sizes = (0..REGISTER_COUNT).map(register_size);
+    if values.len() != sizes.clone().sum::<usize>() {
+        return None;
+    }
+    let mut new = cpu.clone();
+    let mut offset = 0;
+    for (n, size) in sizes.enumerate() {
+        write_register(&mut new, n, &values[offset..offset + size])?;
+        offset += size;
+    }
+    *cpu = new;
+    Some(())
+}
+
+/// Reads memory for an `address,length` m packet. Stops early at the end of
+/// memory, as the protocol allows, but fails if nothing can be read.
+fn read_memory(cpu: &CPU, args: &str) -> Option<String> {
+    let (address, len) = args.split_once(',')?;
+    let (start, len) = (parse_hex(address)?, parse_hex(len)?);
+    let memory = cpu.memory();
+    if start >= memory.len() && len > 0 {
+        return None;
+    }
+    let end = start.saturating_add(len).min(memory.len());
+    Some(encode_hex(&memory[start.min(end)..end]))
+}
+
+/// Writes memory for an `address,length:bytes` M packet.
+fn write_memory(cpu: &mut CPU, args: &str) -> Option<()> {
+    let (range, bytes) = args.split_once(':')?;
+    let (address, len) = range.split_once(',')?;
+    let (start, len) = (parse_hex(address)?, parse_hex(len)?);
+    let bytes = decode_hex(bytes)?;
+    let memory = cpu.memory_mut();
+    if bytes.len() != len || start.checked_add(len)? > memory.len() {
+        return None;
+    }
+    memory[start..start + len].copy_from_slice(&bytes);
+    Some(())
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn parse_hex(s: &str) -> Option<usize> {
+    usize::from_str_radix(s, 16).ok()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn ok_or_error(result: Option<()>) -> String {
+    result.map_or_else(error, |()| "OK".to_string())
+}
+
+fn error() -> String {
+    "E01".to_string()
+}
@@ -0,0 +1,146 @@
+//! Running a machine without a window or audio, and dumping what it drew.
+//! This is what the golden tests and the `headless` subcommand are built on.
+
+use std::fmt;
+
+use crate::chip8::Chip8;
+use crate::cpu::Instruction;
+use crate::display::Display;
+use crate::error::Chip8Error;
+use crate::state;
+
+/// Why a headless run stopped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum End {
+    /// All the requested frames ran.
+    FrameLimit,
+    /// The program reached an instruction that jumps to itself, which it
+    /// can never leave.
+    Loop {
+        address: u16,
+    },
+    /// The program stopped itself with 00FD.
+    Exited,
+    Halted(Chip8Error),
+}
+
+impl fmt::Display for End {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::FrameLimit => write!(f, "frame limit"),
+            Self::Loop { address } => write!(f, "loop at {:03X}", address),
+            Self::Exited => write!(f, "exited"),
+            Self::Halted(error) => write!(f, "halted: {}", error),
+        }
+    }
+}
+
+/// Runs at most `frames` frames of `cycles_per_frame` instructions, each
+/// followed by a timer tick. Returns the number of frames that ran,
+/// including the one that was cut short, and why the run stopped.
+pub fn run(chip8: &mut Chip8, frames: u32, cycles_per_frame: u32) -> (u32, End) {
+    for frame in 1..=frames {
+        for _ in 0..cycles_per_frame {
+            let cpu = chip8.cpu();
+            let address = cpu.instruction_pointer();
+            let instruction = cpu.current_opcode().and_then(Instruction::from_opcode);
+            if instruction == Some(Instruction::Jump { address }) {
+                return (frame, End::Loop { address });
+            }
+            if let Err(error) = chip8.cycle() {
+                return (frame, End::Halted(error));
+            }
+            if chip8.has_exited() {
+                return (frame, End::Exited);
+            }
+        }
+        chip8.tick();
+    }
+    (frames, End::FrameLimit)
+}
+
+/// The display as one line of text per row, from the top: `.` for off
+/// pixels, `#` for plane 1, `o` for plane 2 and `@` for both.
+pub fn text(display: &Display) -> String {
+    let (width, height) = display.dimensions();
+    let mut text = String::with_capacity((width as usize + 1) * height as usize);
+    for y in 0..height {
+        for x in 0..width {
+            text.push(['.', '#', 'o', '@'][display.get_pixel(x, y) as usize]);
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// A hash of the display's size and pixels, to compare frames by.
+pub fn hash(display: &Display) -> u64 {
+    let (width, height) = display.dimensions();
+    let mut data = vec![width, height];
+    data.extend(display.clone().into_inner());
+    state::rom_hash(&data)
+}
+
+/// The display as a PNG image with one image pixel per display pixel,
+/// coloured with `palette` as off, plane 1, plane 2 and both planes.
+pub fn png(display: &Display, palette: &[[u8; 3]; 4]) -> Vec<u8> {
+    let (width, height) = display.dimensions();
+    // Each row starts with filter type 0, no filtering
+    let mut image = Vec::with_capacity((width as usize + 1) * height as usize);
+    for y in 0..height {
+        image.push(0);
+        image.extend((0..width).map(|x| display.get_pixel(x, y)));
+    }
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8-bit palette indices, no interlacing
+    header.extend_from_slice(&[8, 3, 0, 0, 0]);
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"PLTE", &palette.concat());
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&image));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// A zlib stream of uncompressed deflate blocks. Displays are small enough
+/// that compressing them is not worth the code.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(blocks.peek().is_none() as u8);
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    out.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
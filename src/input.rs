@@ -1,28 +1,106 @@
-pub struct Input([bool; 16]);
+/// How many key events are kept between two cycles. Older ones are dropped
+/// when a stopped machine is not taking them.
+const MAX_EVENTS: usize = 32;
+
+/// A change to one key of the keypad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyEvent {
+    Pressed(u8),
+    Released(u8),
+}
+
+/// The hex keypad: which keys are held, and the presses and releases since
+/// the last `Chip8::cycle`, so that a key tapped between two cycles is not
+/// missed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Input {
+    keys: [bool; 16],
+    events: Vec<KeyEvent>,
+}
 
 impl Input {
     pub fn new() -> Self {
-        Self([false; 16])
+        Self {
+            keys: [false; 16],
+            events: Vec::new(),
+        }
     }
 
+    /// Holds `key`. Pressing a key that is already held does nothing.
     pub fn key_pressed(&mut self, key: u8) {
-        self.0[key as usize] = true;
+        if !self.keys[key as usize] {
+            self.keys[key as usize] = true;
+            self.push(KeyEvent::Pressed(key));
+        }
     }
 
+    /// Lets go of `key`. Releasing a key that is not held does nothing.
     pub fn key_released(&mut self, key: u8) {
-        self.0[key as usize] = false;
+        if self.keys[key as usize] {
+            self.keys[key as usize] = false;
+            self.push(KeyEvent::Released(key));
+        }
+    }
+
+    fn push(&mut self, event: KeyEvent) {
+        if self.events.len() == MAX_EVENTS {
+            self.events.remove(0);
+        }
+        self.events.push(event);
     }
 
     pub fn is_key_pressed(&self, key: u8) -> bool {
-        self.0[key as usize]
+        self.keys[key as usize]
+    }
+
+    /// The held keys as a bitmask, key 0 in the lowest bit.
+    pub fn bits(&self) -> u16 {
+        (0..16)
+            .filter(|&key| self.keys[key])
+            .map(|key| 1 << key)
+            .sum()
+    }
+
+    /// A keypad holding the keys in `bits`, with no events.
+    pub fn from_bits(bits: u16) -> Self {
+        let mut input = Self::new();
+        for key in 0..16 {
+            input.keys[key] = bits & (1 << key) != 0;
+        }
+        input
     }
 
     pub fn get_pressed_key(&self) -> Option<u8> {
-        for key in 0..self.0.len() {
-            if self.0[key] {
+        for key in 0..self.keys.len() {
+            if self.keys[key] {
                 return Some(key as u8);
             }
         }
         None
     }
+
+    /// The presses and releases since the events were last cleared, oldest
+    /// first.
+    pub fn events(&self) -> &[KeyEvent] {
+        &self.events
+    }
+
+    /// The first key released since the events were last cleared.
+    pub fn get_released_key(&self) -> Option<u8> {
+        self.events.iter().find_map(|event| match *event {
+            KeyEvent::Released(key) => Some(key),
+            KeyEvent::Pressed(_) => None,
+        })
+    }
+
+    /// Forgets the events. `Chip8::cycle` does this after every instruction.
+    pub fn clear_events(&mut self) {
+        self.events.clear();
+    }
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Self::new()
+    }
 }
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+#[cfg(feature = "gamepad")]
+use gilrs::Button;
+use glium::glutin::event::VirtualKeyCode;
+use serde::Deserialize;
+
+/// Without gamepad support, buttons in the config file are accepted but not
+/// checked or used.
+#[cfg(not(feature = "gamepad"))]
+type Button = toml::Value;
+
+const DEFAULT_CONFIG: &str = include_str!("default_config.toml");
+
+/// Host inputs by CHIP-8 key, as written in the config file.
+type Table<T> = HashMap<String, Vec<T>>;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    #[serde(default)]
+    keys: Table<VirtualKeyCode>,
+    #[serde(default)]
+    buttons: Table<Button>,
+    /// Settings for single ROMs, by ROM hash.
+    #[serde(default)]
+    roms: HashMap<String, RomConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RomConfig {
+    #[serde(default)]
+    keys: Table<VirtualKeyCode>,
+    #[serde(default)]
+    buttons: Table<Button>,
+}
+
+/// The host inputs one table sets for each CHIP-8 key, if any.
+type Layer<T> = [Option<Vec<T>>; 16];
+
+/// Which host keys and gamepad buttons press which CHIP-8 keys. A CHIP-8
+/// key stays pressed as long as any of them is held.
+#[derive(Debug)]
+pub struct Keymap {
+    keys: [Vec<VirtualKeyCode>; 16],
+    buttons: [Vec<Button>; 16],
+    held_keys: Vec<VirtualKeyCode>,
+    held_buttons: Vec<Button>,
+}
+
+impl Keymap {
+    /// Reads the mapping for the ROM with `rom_hash` from the config file at
+    /// `path`, or from the one in the user's config directory if there is no
+    /// `path`. Keys the file does not set keep their built-in default.
+    pub fn load(path: Option<&Path>, rom_hash: u64) -> Result<Self> {
+        let mut layers = parse(DEFAULT_CONFIG, rom_hash).expect("invalid default config");
+        let path = path
+            .map(Path::to_owned)
+            .or_else(|| default_config_path().filter(|path| path.exists()));
+        if let Some(path) = path {
+            let source = fs::read_to_string(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            let file = parse(&source, rom_hash)
+                .with_context(|| format!("invalid config file {}", path.display()))?;
+            layers.extend(file);
+        }
+        let (keys, buttons): (Vec<_>, Vec<_>) = layers.into_iter().unzip();
+        Ok(Self {
+            keys: merge(keys),
+            buttons: merge(buttons),
+            held_keys: Vec::new(),
+            held_buttons: Vec::new(),
+        })
+    }
+
+    /// The CHIP-8 keys a host key press or release changed, with whether
+    /// each is now pressed.
+    pub fn key_event(&mut self, key: VirtualKeyCode, pressed: bool) -> Vec<(u8, bool)> {
+        self.event(|keymap| hold(&mut keymap.held_keys, key, pressed))
+    }
+
+    /// Like `key_event`, for gamepad buttons.
+    #[cfg(feature = "gamepad")]
+    pub fn button_event(&mut self, button: Button, pressed: bool) -> Vec<(u8, bool)> {
+        self.event(|keymap| hold(&mut keymap.held_buttons, button, pressed))
+    }
+
+    fn event(&mut self, update: impl FnOnce(&mut Self)) -> Vec<(u8, bool)> {
+        let before: Vec<bool> = (0..16).map(|k| self.is_pressed(k)).collect();
+        update(self);
+        (0..16)
+            .filter(|&k| self.is_pressed(k) != before[k])
+            .map(|k| (k as u8, !before[k]))
+            .collect()
+    }
+
+    fn is_pressed(&self, key: usize) -> bool {
+        self.keys[key].iter().any(|k| self.held_keys.contains(k))
+            || self.buttons[key]
+                .iter()
+                .any(|b| self.held_buttons.contains(b))
+    }
+}
+
+fn hold<T: PartialEq>(held: &mut Vec<T>, input: T, pressed: bool) {
+    if !pressed {
+        held.retain(|held| *held != input);
+    } else if !held.contains(&input) {
+        held.push(input);
+    }
+}
+
+/// Parses a config file into the key and button layers that apply to the
+/// ROM with `rom_hash`: the general tables, then the ROM's own.
+fn parse(source: &str, rom_hash: u64) -> Result<Vec<(Layer<VirtualKeyCode>, Layer<Button>)>> {
+    let config: Config = toml::from_str(source)?;
+    let mut layers = vec![(layer(config.keys)?, layer(config.buttons)?)];
+    for (hash, rom) in config.roms {
+        let hash = u64::from_str_radix(&hash, 16)
+            .map_err(|_| anyhow!("expected a ROM hash in hex, got `{}`", hash))?;
+        // Other ROMs' tables are still read, to catch mistakes early
+        let rom_layers = (layer(rom.keys)?, layer(rom.buttons)?);
+        if hash == rom_hash {
+            layers.push(rom_layers);
+        }
+    }
+    Ok(layers)
+}
+
+fn layer<T>(table: Table<T>) -> Result<Layer<T>> {
+    let mut layer = Layer::default();
+    for (key, inputs) in table {
+        let index = match u8::from_str_radix(&key, 16) {
+            Ok(index) if key.len() == 1 => index,
+            _ => return Err(anyhow!("expected a CHIP-8 key from 0 to F, got `{}`", key)),
+        };
+        layer[index as usize] = Some(inputs);
+    }
+    Ok(layer)
+}
+
+/// Applies the layers in order. Each CHIP-8 key a layer sets gets its inputs,
+/// which are taken away from the keys earlier layers gave them to.
+fn merge<T: PartialEq>(layers: Vec<Layer<T>>) -> [Vec<T>; 16] {
+    let mut keys: [Vec<T>; 16] = Default::default();
+    for layer in layers {
+        for inputs in layer.iter().flatten() {
+            for key in keys.iter_mut() {
+                key.retain(|input| !inputs.contains(input));
+            }
+        }
+        for (key, inputs) in keys.iter_mut().zip(layer) {
+            if let Some(inputs) = inputs {
+                *key = inputs;
+            }
+        }
+    }
+    keys
+}
+
+fn default_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("chip8").join("config.toml"))
+}
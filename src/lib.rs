@@ -0,0 +1,34 @@
+//! A headless CHIP-8 interpreter.
+//!
+//! The core has no window or audio dependencies: drive a [`Chip8`] with
+//! `cycle` and `tick`, feed it keypad state, and read back its [`Display`]
+//! and sound state however the host likes. The [`debugger`] module wraps a
+//! machine with breakpoints and stepping, which [`gdb`] exposes to GDB
+//! clients. [`movie`] records and replays runs, [`rewind`] steps back
+//! through the last frames, and [`trace`] logs every executed instruction.
+
+pub mod assembler;
+mod chip8;
+mod cpu;
+pub mod debugger;
+pub mod disassembler;
+mod display;
+mod error;
+pub mod gdb;
+pub mod headless;
+mod input;
+pub mod movie;
+pub mod octo;
+mod quirks;
+pub mod rewind;
+mod rng;
+mod state;
+pub mod trace;
+
+pub use crate::chip8::Chip8;
+pub use crate::cpu::{AccessKind, Instruction, MemoryAccess, CPU};
+pub use crate::display::Display;
+pub use crate::error::Chip8Error;
+pub use crate::input::{Input, KeyEvent};
+pub use crate::quirks::{IndexIncrement, Quirks};
+pub use crate::state::{rom_hash, SaveStateError};
@@ -1,50 +1,217 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::net::TcpListener;
+use std::path::Path;
 use std::time::Instant;
 
+use anyhow::{Context, Result};
 use glium::glutin;
-use glium::{Display, Rect};
+use glium::Display;
 use glutin::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
 use glutin::event_loop::{ControlFlow, EventLoop};
 use glutin::window::{Fullscreen, WindowBuilder};
 use glutin::{Api, ContextBuilder, GlProfile, GlRequest};
+use structopt::StructOpt;
+
+use chip8::debugger::{self, Debugger};
+use chip8::gdb::{self, GdbStub};
+use chip8::movie::Movie;
+use chip8::rewind::Rewind;
+use chip8::trace::Tracer;
+use chip8::{assembler, disassembler, headless, octo, rom_hash, Chip8};
 
 mod audio;
-mod chip8;
-mod cpu;
-mod display;
-mod input;
+mod cli;
+#[cfg(feature = "gamepad")]
+mod gamepad;
+mod keymap;
+mod recording;
 mod renderer;
+mod repl;
+mod rpl;
+mod slots;
 
-use chip8::Chip8;
+use audio::Audio;
+use cli::{
+    AsmOptions, Command, DisasmOptions, HashOptions, HeadlessOptions, MachineOptions,
+    ReplayOptions, RunOptions, TraceOptions,
+};
+#[cfg(feature = "gamepad")]
+use gamepad::Gamepads;
+use keymap::Keymap;
+use recording::Recording;
 use renderer::Renderer;
+use repl::Repl;
+use rpl::RplFlagsFile;
+use slots::SaveSlots;
+
+fn main() -> Result<()> {
+    match Command::from_args() {
+        Command::Run(options) => run(options),
+        Command::Disasm(options) => disasm(options),
+        Command::Asm(options) => asm(options),
+        Command::Headless(options) => headless(options),
+        Command::Replay(options) => replay(options),
+        Command::Hash(options) => hash(options),
+    }
+}
+
+fn asm(options: AsmOptions) -> Result<()> {
+    let source = fs::read_to_string(&options.source)
+        .with_context(|| format!("failed to read {}", options.source.display()))?;
+    let program = assembler::assemble(&source, &options.source)?;
+    let output = options.output_path();
+    fs::write(&output, program).with_context(|| format!("failed to write {}", output.display()))
+}
+
+fn disasm(options: DisasmOptions) -> Result<()> {
+    let program = fs::read(&options.rom)
+        .with_context(|| format!("failed to read ROM {}", options.rom.display()))?;
+    print!("{}", disassembler::disassemble(&program, options.syntax));
+    Ok(())
+}
 
-const ASPECT_RATIO: f32 = 2.0 / 1.0;
+fn replay(options: ReplayOptions) -> Result<()> {
+    let data = fs::read(&options.movie)
+        .with_context(|| format!("failed to read {}", options.movie.display()))?;
+    let movie = Movie::from_bytes(&data)
+        .with_context(|| format!("failed to load movie {}", options.movie.display()))?;
+    let chip8 = movie.play()?;
+    print!("{}", headless::text(chip8.display()));
+    eprintln!("played {} frames", movie.frames.len());
+    if let Some(error) = chip8.error() {
+        eprintln!("chip8 halted: {}", error);
+    }
+    Ok(())
+}
 
-const CYCLE_HZ: u32 = 720;
-const TICK_HZ: u32 = 60;
+fn hash(options: HashOptions) -> Result<()> {
+    let program = load_program(&options.rom, &options.machine)?;
+    println!("{:016x}", rom_hash(&program));
+    Ok(())
+}
 
-const CYCLE_TIME: f32 = 1.0 / CYCLE_HZ as f32;
-const TICK_TIME: f32 = 1.0 / TICK_HZ as f32;
+/// Reads a ROM, compiling it first if it is an Octo program.
+fn load_program(rom: &Path, machine: &MachineOptions) -> Result<Vec<u8>> {
+    if rom.extension() == Some("8o".as_ref()) {
+        let source =
+            fs::read_to_string(rom).with_context(|| format!("failed to read {}", rom.display()))?;
+        Ok(octo::compile(&source, rom, machine.platform())?)
+    } else {
+        fs::read(rom).with_context(|| format!("failed to read ROM {}", rom.display()))
+    }
+}
 
-// const PROGRAM: &[u8] = include_bytes!("../roms/games/Lunar Lander (Udo Pernisz, 1979).ch8");
-// const PROGRAM: &[u8] = include_bytes!("../roms/games/Tetris [Fran Dachille, 1991].ch8");
-// const PROGRAM: &[u8] = include_bytes!("../roms/glitchGhost.ch8");
-const PROGRAM: &[u8] = include_bytes!("../roms/games/Pong [Paul Vervalin, 1990].ch8");
+/// Starts tracing `chip8` if a trace file was given.
+fn start_trace(chip8: &mut Chip8, options: &TraceOptions) -> Result<()> {
+    if let Some(path) = &options.trace {
+        let file =
+            File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+        let mut tracer = Tracer::new(Box::new(BufWriter::new(file)), options.trace_history);
+        tracer.set_range(options.trace_range.clone());
+        chip8.set_tracer(Some(tracer));
+    }
+    Ok(())
+}
+
+fn finish_trace(chip8: &mut Chip8) -> Result<()> {
+    match chip8.take_tracer() {
+        Some(tracer) => tracer.finish().context("failed to write the trace"),
+        None => Ok(()),
+    }
+}
+
+fn headless(options: HeadlessOptions) -> Result<()> {
+    let program = load_program(&options.rom, &options.machine)?;
+    let mut chip8 = Chip8::new(&program, options.machine.quirks())
+        .with_context(|| format!("failed to load ROM {}", options.rom.display()))?;
+    chip8.set_seed(options.seed);
+    start_trace(&mut chip8, &options.trace)?;
+    let (frames, end) = headless::run(&mut chip8, options.frames, options.cycles_per_frame);
+    finish_trace(&mut chip8)?;
+    if !options.hash_only {
+        print!("{}", headless::text(chip8.display()));
+        eprintln!("ran {} frames, stopped at {}", frames, end);
+    }
+    println!("{:016x}", headless::hash(chip8.display()));
+    if let Some(path) = &options.png {
+        let png = headless::png(chip8.display(), &options.palette.to_rgb8());
+        fs::write(path, png).with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+fn run(options: RunOptions) -> Result<()> {
+    let program = load_program(&options.rom, &options.machine)?;
+    let mut chip8 = Chip8::new(&program, options.machine.quirks())
+        .with_context(|| format!("failed to load ROM {}", options.rom.display()))?;
+    let mut keymap = Keymap::load(options.config.as_deref(), chip8.rom_hash())?;
+    let mut rpl_flags = RplFlagsFile::new(options.rpl_flags_path());
+    chip8.set_rpl_flags(rpl_flags.load()?);
+    let seed = options.seed.unwrap_or_else(rand::random);
+    chip8.set_seed(seed);
+    start_trace(&mut chip8, &options.trace)?;
+    let mut recording = options
+        .record
+        .clone()
+        .map(|path| Recording::new(path, &program, options.machine.quirks(), seed));
+    let mut slots = SaveSlots::new(&options.rom);
+    let frames = options.rewind_seconds.saturating_mul(options.tick_rate);
+    let mut rewind = Rewind::new(frames as usize);
+    let mut rewinding = false;
+
+    let mut gdb = match options.gdb {
+        Some(port) => {
+            let listener = TcpListener::bind(("127.0.0.1", port))
+                .with_context(|| format!("failed to listen on port {}", port))?;
+            eprintln!("waiting for GDB to connect to 127.0.0.1:{}", port);
+            let (stream, _) = listener.accept().context("failed to accept GDB")?;
+            Some(GdbStub::new(stream).context("failed to set up the GDB connection")?)
+        }
+        None => None,
+    };
+
+    let cycle_time = 1.0 / options.cycles_per_second as f32;
+    let tick_time = 1.0 / options.tick_rate as f32;
 
-fn main() {
     let event_loop = EventLoop::new();
-    let monitor = event_loop.primary_monitor();
-    let window_builder = WindowBuilder::new()
+    // The size is also the one the window gets when leaving fullscreen. The
+    // renderer resizes the window when the display changes resolution.
+    let dimensions = chip8.display().dimensions();
+    let mut window_builder = WindowBuilder::new()
         .with_visible(false)
         .with_title("chip8")
-        .with_fullscreen(Some(Fullscreen::Borderless(monitor)));
+        .with_inner_size(renderer::window_size(dimensions, options.scale));
+    if !options.windowed {
+        let monitor = event_loop.primary_monitor();
+        window_builder = window_builder.with_fullscreen(Some(Fullscreen::Borderless(monitor)));
+    }
     let context_builder = ContextBuilder::new()
         .with_gl(GlRequest::Specific(Api::OpenGl, (3, 3)))
         .with_gl_profile(GlProfile::Core)
         .with_vsync(true);
-    let display = Display::new(window_builder, context_builder, &event_loop).unwrap();
+    let display = Display::new(window_builder, context_builder, &event_loop)
+        .context("failed to create window")?;
+
+    let mut renderer = Renderer::new(display, options.palette, options.scale, dimensions);
+    let audio = Audio::new();
+    #[cfg(feature = "gamepad")]
+    let mut gamepads = Gamepads::new()
+        .inspect_err(|error| eprintln!("gamepads are not available: {:#}", error))
+        .ok();
 
-    let mut chip8 = Chip8::new(PROGRAM);
-    let mut renderer = Renderer::new(display);
+    let mut debugger = Debugger::new();
+    if gdb.is_some() {
+        debugger.pause();
+    }
+    let repl = if options.debug {
+        debugger.pause();
+        let repl = Repl::spawn();
+        repl.print(&debugger.execute(debugger::Command::Registers, &mut chip8));
+        Some(repl)
+    } else {
+        None
+    };
 
     let mut prev_t = Instant::now();
     let mut tick_dt = 0.0;
@@ -66,26 +233,42 @@ fn main() {
                     (ElementState::Pressed, VirtualKeyCode::Escape) => {
                         *control_flow = ControlFlow::Exit
                     }
-                    (ElementState::Pressed, key) => {
-                        if let Some(k) = keymap(key) {
-                            chip8.key_pressed(k)
+                    (state, VirtualKeyCode::Back) => {
+                        if recording.is_none() {
+                            rewinding = state == ElementState::Pressed;
+                        } else if state == ElementState::Pressed {
+                            eprintln!("rewinding is not available while recording");
                         }
                     }
-                    (ElementState::Released, key) => {
-                        if let Some(k) = keymap(key) {
-                            chip8.key_released(k)
+                    (ElementState::Pressed, VirtualKeyCode::F5) => match slots.save(&chip8) {
+                        Ok(()) => eprintln!("saved state to slot {}", slots.slot()),
+                        Err(error) => eprintln!("{:#}", error),
+                    },
+                    (ElementState::Pressed, VirtualKeyCode::F9) if recording.is_some() => {
+                        eprintln!("loading states is not available while recording")
+                    }
+                    (ElementState::Pressed, VirtualKeyCode::F9) => match slots.load(&mut chip8) {
+                        Ok(()) => {
+                            rewind.clear();
+                            eprintln!("loaded state from slot {}", slots.slot())
                         }
+                        Err(error) => eprintln!("{:#}", error),
+                    },
+                    (ElementState::Pressed, VirtualKeyCode::F6) => {
+                        slots.previous_slot();
+                        eprintln!("save state slot {}", slots.slot());
+                    }
+                    (ElementState::Pressed, VirtualKeyCode::F7) => {
+                        slots.next_slot();
+                        eprintln!("save state slot {}", slots.slot());
+                    }
+                    (ElementState::Pressed, VirtualKeyCode::F11) => renderer.toggle_fullscreen(),
+                    (state, key) => {
+                        let changes = keymap.key_event(key, state == ElementState::Pressed);
+                        press_keys(changes, &mut chip8, &mut recording);
                     }
                 },
-                WindowEvent::Resized(window_size) => {
-                    let height = (ASPECT_RATIO.recip() * window_size.width as f32) as u32;
-                    renderer.set_viewport(Rect {
-                        left: 0,
-                        bottom: (window_size.height - height) / 2,
-                        width: window_size.width,
-                        height: height,
-                    });
-                }
+                WindowEvent::Resized(window_size) => renderer.set_window_size(window_size),
                 _ => {}
             },
             Event::MainEventsCleared => {
@@ -95,27 +278,112 @@ fn main() {
                 tick_dt += dt;
                 cycle_dt += dt;
 
-                while cycle_dt > CYCLE_TIME {
-                    chip8.cycle();
-                    cycle_dt -= CYCLE_TIME;
+                #[cfg(feature = "gamepad")]
+                if let Some(gamepads) = &mut gamepads {
+                    for (button, pressed) in gamepads.events() {
+                        let changes = keymap.button_event(button, pressed);
+                        press_keys(changes, &mut chip8, &mut recording);
+                    }
+                }
+
+                if let Some(repl) = &repl {
+                    while let Some(command) = repl.try_recv() {
+                        repl.print(&debugger.execute(command, &mut chip8));
+                    }
+                }
+
+                let mut stops = Vec::new();
+                while cycle_dt > cycle_time {
+                    if !chip8.is_halted() && !rewinding {
+                        match debugger.cycle(&mut chip8) {
+                            Ok(stop) => stops.extend(stop),
+                            Err(error) => eprintln!("chip8 halted: {}", error),
+                        }
+                        if let Some(recording) = &mut recording {
+                            recording.cycle();
+                        }
+                    }
+                    cycle_dt -= cycle_time;
+                }
+
+                while tick_dt > tick_time {
+                    if rewinding {
+                        rewind.step_back(&mut chip8);
+                    } else {
+                        let paused = debugger.is_paused();
+                        stops.extend(debugger.tick(&mut chip8));
+                        if let Some(recording) = &mut recording {
+                            recording.end_frame(&mut chip8);
+                        }
+                        if !paused && !chip8.is_halted() {
+                            rewind.push(&chip8);
+                        }
+                    }
+                    tick_dt -= tick_time;
                 }
 
-                while tick_dt > TICK_TIME {
-                    chip8.tick();
-                    tick_dt -= TICK_TIME;
+                // After running, so the client hears about stops and exits
+                if let Some(stub) = &mut gdb {
+                    match stub.poll(&mut debugger, &mut chip8) {
+                        Ok(gdb::Status::Attached) => {}
+                        Ok(gdb::Status::Detached) => {
+                            eprintln!("GDB detached");
+                            gdb = None;
+                        }
+                        Ok(gdb::Status::Killed) => *control_flow = ControlFlow::Exit,
+                        Err(error) => {
+                            eprintln!("lost the GDB connection: {}", error);
+                            gdb = None;
+                        }
+                    }
+                }
+
+                if let Some(repl) = &repl {
+                    for stop in stops {
+                        let state = debugger::format_state(chip8.cpu());
+                        repl.print(&format!("\n{}\n{}", stop, state));
+                    }
+                }
+
+                if let Err(error) = rpl_flags.save(chip8.rpl_flags()) {
+                    eprintln!("{:#}", error);
+                }
+                if chip8.has_exited() {
+                    *control_flow = ControlFlow::Exit;
+                }
+
+                audio.set_pattern(chip8.audio_pattern(), chip8.audio_sample_rate());
+                if chip8.should_play_sound() && !debugger.is_paused() && !rewinding {
+                    audio.play();
+                } else {
+                    audio.pause();
                 }
 
                 renderer.render(chip8.display());
             }
+            Event::LoopDestroyed => {
+                if let Some(recording) = &recording {
+                    if let Err(error) = recording.save() {
+                        eprintln!("{:#}", error);
+                    }
+                }
+                if let Err(error) = finish_trace(&mut chip8) {
+                    eprintln!("{:#}", error);
+                }
+            }
             _ => {}
         }
     });
 }
 
-fn keymap(key: VirtualKeyCode) -> Option<u8> {
-    use VirtualKeyCode::*;
-    [X, Key1, Key2, Key3, Q, W, E, A, S, D, Z, C, Key4, R, F, V]
-        .iter()
-        .position(|&k| k == key)
-        .map(|i| i as u8)
+/// Presses and releases CHIP-8 keys, through the recording if there is one.
+fn press_keys(changes: Vec<(u8, bool)>, chip8: &mut Chip8, recording: &mut Option<Recording>) {
+    for (key, pressed) in changes {
+        match (recording.as_mut(), pressed) {
+            (Some(recording), true) => recording.key_pressed(key),
+            (Some(recording), false) => recording.key_released(key),
+            (None, true) => chip8.key_pressed(key),
+            (None, false) => chip8.key_released(key),
+        }
+    }
 }
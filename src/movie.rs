@@ -0,0 +1,165 @@
+//! Input movies: the keypad state of every frame of a run, together with
+//! everything else the run depended on, so that it can be played back to
+//! exactly the same machine state.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::chip8::Chip8;
+use crate::error::Chip8Error;
+use crate::input::Input;
+use crate::quirks::Quirks;
+use crate::state::{self, SaveStateError, StateReader, StateWriter};
+
+const MAGIC: &[u8; 4] = b"C8MV";
+const VERSION: u16 = 4;
+
+/// What happened between two timer ticks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    /// The pressed keys as an [`Input::bits`] bitmask.
+    pub keys: u16,
+    /// The number of times the machine was cycled before the tick.
+    pub cycles: u32,
+}
+
+/// A recorded run, played from power-on.
+///
+/// The program itself is part of the movie, so a movie file is all that is
+/// needed to reproduce a run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub program: Vec<u8>,
+    pub quirks: Quirks,
+    pub seed: u64,
+    pub frames: Vec<Frame>,
+}
+
+/// Why a movie could not be read or played.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieError {
+    NotAMovie,
+    UnsupportedVersion {
+        version: u16,
+    },
+    Corrupt,
+    /// The program could not be loaded with the movie's quirks.
+    Load(Chip8Error),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotAMovie => write!(f, "not a movie"),
+            Self::UnsupportedVersion { version } => write!(
+                f,
+                "movie version {} is not supported, expected version {}",
+                version, VERSION
+            ),
+            Self::Corrupt => write!(f, "movie is corrupt"),
+            Self::Load(error) => write!(f, "failed to load the movie's program: {}", error),
+        }
+    }
+}
+
+impl Error for MovieError {}
+
+impl Movie {
+    /// An empty movie of `program` run with `quirks` and CXNN seeded with
+    /// `seed`.
+    pub fn new(program: &[u8], quirks: Quirks, seed: u64) -> Self {
+        Self {
+            program: program.to_vec(),
+            quirks,
+            seed,
+            frames: Vec::new(),
+        }
+    }
+
+    /// The machine as the movie starts, before the first frame.
+    pub fn start(&self) -> Result<Chip8, MovieError> {
+        let mut chip8 = Chip8::new(&self.program, self.quirks).map_err(MovieError::Load)?;
+        chip8.set_seed(self.seed);
+        Ok(chip8)
+    }
+
+    /// Runs the whole movie and returns the machine as it ends.
+    ///
+    /// Runtime errors halt the machine as they would during recording, after
+    /// which the remaining cycles do nothing.
+    pub fn play(&self) -> Result<Chip8, MovieError> {
+        let mut chip8 = self.start()?;
+        for frame in &self.frames {
+            play_frame(&mut chip8, frame);
+        }
+        Ok(chip8)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.bytes(MAGIC);
+        writer.u16(VERSION);
+        writer.u64(state::rom_hash(&self.program));
+        writer.sized_bytes(&self.program);
+        writer.quirks(&self.quirks);
+        writer.u64(self.seed);
+        writer.u64(self.frames.len() as u64);
+        for frame in &self.frames {
+            writer.u16(frame.keys);
+            writer.u32(frame.cycles);
+        }
+        writer.into_inner()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, MovieError> {
+        let mut reader = StateReader::new(data);
+        if reader.bytes(MAGIC.len()) != Ok(&MAGIC[..]) {
+            return Err(MovieError::NotAMovie);
+        }
+        let version = reader.u16().map_err(|_| MovieError::Corrupt)?;
+        if version != VERSION {
+            return Err(MovieError::UnsupportedVersion { version });
+        }
+        read_movie(&mut reader).map_err(|_| MovieError::Corrupt)
+    }
+}
+
+/// Sets the frame's keys, cycles the machine and ticks the timers, the same
+/// order in which a frame is recorded.
+pub fn play_frame(chip8: &mut Chip8, frame: &Frame) {
+    chip8.set_input(Input::from_bits(frame.keys));
+    for _ in 0..frame.cycles {
+        if chip8.is_halted() {
+            break;
+        }
+        let _ = chip8.cycle();
+    }
+    chip8.tick();
+}
+
+fn read_movie(reader: &mut StateReader) -> Result<Movie, SaveStateError> {
+    let hash = reader.u64()?;
+    let program = reader.sized_bytes()?.to_vec();
+    if state::rom_hash(&program) != hash {
+        return Err(SaveStateError::Corrupt);
+    }
+    let quirks = reader.quirks()?;
+    let seed = reader.u64()?;
+    let frame_count = reader.u64()?;
+    let mut frames = Vec::new();
+    for _ in 0..frame_count {
+        frames.push(Frame {
+            keys: reader.u16()?,
+            cycles: reader.u32()?,
+        });
+    }
+    if !reader.is_empty() {
+        return Err(SaveStateError::Corrupt);
+    }
+    Ok(Movie {
+        program,
+        quirks,
+        seed,
+        frames,
+    })
+}
@@ -0,0 +1,1199 @@
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::f64::consts::{E, PI};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::assembler::AssemblerError;
+use crate::cpu::{Instruction, PROGRAM_START};
+
+const MEMORY_SIZE: usize = 0x1000;
+const EXTENDED_MEMORY_SIZE: usize = 0x10000;
+const MAX_MACRO_EXPANSIONS: usize = 10_000;
+const MAX_INCLUDED_FILES: usize = 256;
+
+/// The instruction set a program may use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Platform {
+    Chip8,
+    SuperChip,
+    /// XO-CHIP, which also has 64 KB of memory.
+    XoChip,
+}
+
+impl Platform {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Chip8 => "CHIP-8",
+            Self::SuperChip => "SUPER-CHIP",
+            Self::XoChip => "XO-CHIP",
+        }
+    }
+}
+
+/// Compiles an Octo program into a ROM to be loaded at 0x200.
+///
+/// This covers the language as documented for Octo: labels, `:proc`,
+/// `:const`, `:alias`, `:calc`, `:macro`, `:byte`, `:pointer`, `:org`,
+/// `:next`, `:unpack` and `:include`, every statement of the instruction
+/// set, `if ... then`, `if ... begin ... else ... end`, `loop ... while ...
+/// again`, and the `<`, `>`, `<=` and `>=` comparisons, which use VF.
+/// `:breakpoint` and `:monitor` are accepted and ignored.
+///
+/// Instructions beyond `platform` are errors. `path` is used to resolve
+/// includes and in error messages.
+pub fn compile(
+    source: &str,
+    path: &Path,
+    platform: Platform,
+) -> std::result::Result<Vec<u8>, AssemblerError> {
+    let mut compiler = Compiler {
+        files: vec![path.to_path_buf()],
+        tokens: tokenize(source, 0),
+        platform,
+        rom: Vec::new(),
+        here: PROGRAM_START,
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+        expansions: 0,
+        last: Token {
+            text: String::new(),
+            file: 0,
+            line: 1,
+        },
+    };
+    compiler.compile()?;
+    Ok(compiler.rom)
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    /// Index into `Compiler::files`.
+    file: usize,
+    line: usize,
+}
+
+fn tokenize(source: &str, file: usize) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (i, line) in source.lines().enumerate() {
+        let mut rest = line;
+        while let Some(start) = rest.find(|c: char| !c.is_whitespace()) {
+            rest = &rest[start..];
+            if rest.starts_with('#') {
+                break;
+            }
+            let end = if let Some(string) = rest.strip_prefix('"') {
+                string.find('"').map_or(rest.len(), |end| end + 2)
+            } else if rest.starts_with(['{', '}', '(', ')']) {
+                1
+            } else {
+                rest.find(|c: char| c.is_whitespace() || "{}()".contains(c))
+                    .unwrap_or(rest.len())
+            };
+            tokens.push_back(Token {
+                text: rest[..end].to_string(),
+                file,
+                line: i + 1,
+            });
+            rest = &rest[end..];
+        }
+    }
+    tokens
+}
+
+/// A reference to a label that was not defined yet when it was used.
+struct Fixup {
+    address: usize,
+    kind: FixupKind,
+    token: Token,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FixupKind {
+    /// The low 12 bits of the instruction at the address.
+    Address,
+    /// The whole word at the address.
+    Long,
+    /// The second bytes of `v0 := N` and `v1 := NN` emitted by `:unpack`,
+    /// which start at the address.
+    Unpack(u8),
+}
+
+enum Block {
+    /// `if ... begin`, with the address of the jump past the block.
+    If { jump: usize, token: Token },
+    /// `else`, with the address of the jump past the block.
+    Else { jump: usize, token: Token },
+    /// `loop`, with its start and the addresses of the jumps out of it
+    /// emitted by `while`.
+    Loop {
+        start: usize,
+        breaks: Vec<usize>,
+        token: Token,
+    },
+}
+
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// The right-hand side of a comparison.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    Register(u8),
+    Constant(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+    Key,
+    NotKey,
+}
+
+impl Comparison {
+    fn negate(self) -> Self {
+        match self {
+            Self::Equal => Self::NotEqual,
+            Self::NotEqual => Self::Equal,
+            Self::Less => Self::GreaterOrEqual,
+            Self::Greater => Self::LessOrEqual,
+            Self::LessOrEqual => Self::Greater,
+            Self::GreaterOrEqual => Self::Less,
+            Self::Key => Self::NotKey,
+            Self::NotKey => Self::Key,
+        }
+    }
+}
+
+struct Condition {
+    register: u8,
+    comparison: Comparison,
+    operand: Operand,
+}
+
+struct Compiler {
+    files: Vec<PathBuf>,
+    tokens: VecDeque<Token>,
+    platform: Platform,
+    /// The program from 0x200 onwards.
+    rom: Vec<u8>,
+    /// The address of the next byte to emit.
+    here: usize,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    expansions: usize,
+    /// The last token read, for errors at the end of the source.
+    last: Token,
+}
+
+type Result<T> = std::result::Result<T, AssemblerError>;
+
+impl Compiler {
+    fn compile(&mut self) -> Result<()> {
+        // The program starts with a jump to main, filled in at the end
+        self.emit(Instruction::Jump { address: 0 })?;
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+        if let Some(block) = self.blocks.pop() {
+            let (token, message) = match block {
+                Block::If { token, .. } | Block::Else { token, .. } => {
+                    (token, "`begin` without `end`")
+                }
+                Block::Loop { token, .. } => (token, "`loop` without `again`"),
+            };
+            return Err(self.error_at(&token, message.to_string()));
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            let target = match self.labels.get(&fixup.token.text) {
+                Some(&target) => target,
+                None => {
+                    let message = format!("unknown label `{}`", fixup.token.text);
+                    return Err(self.error_at(&fixup.token, message));
+                }
+            };
+            self.patch(fixup.address, fixup.kind, target)
+                .map_err(|message| self.error_at(&fixup.token, message))?;
+        }
+        let main = match self.labels.get("main") {
+            Some(&main) => main,
+            None => {
+                let message = "the program has no `main` label".to_string();
+                return Err(self.error_at(&self.last.clone(), message));
+            }
+        };
+        self.patch(PROGRAM_START, FixupKind::Address, main)
+            .map_err(|message| self.error_at(&self.last.clone(), message))
+    }
+
+    fn statement(&mut self) -> Result<()> {
+        let token = self.next()?;
+        if let Some(register) = self.register(&token.text) {
+            return self.register_statement(register, &token);
+        }
+        match token.text.as_str() {
+            ":" | ":proc" => {
+                let name = self.next()?;
+                self.define_label(&name, self.here)
+            }
+            ":next" => {
+                let name = self.next()?;
+                self.define_label(&name, self.here + 1)
+            }
+            ":const" => {
+                let name = self.next()?;
+                let value = self.next()?;
+                let value = self.value(&value)?;
+                self.define_constant(&name, value)
+            }
+            ":calc" => {
+                let name = self.next()?;
+                let value = self.calc()?;
+                self.define_constant(&name, value)
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let register = self.next()?;
+                let register = self.expect_register(&register)?;
+                self.check_name(&name)?;
+                self.aliases.insert(name.text, register);
+                Ok(())
+            }
+            ":macro" => self.define_macro(),
+            ":byte" => {
+                let value = if self.peek_is("{") {
+                    self.calc()?
+                } else {
+                    let token = self.next()?;
+                    self.value(&token)?
+                };
+                let byte = self.fit_byte(value, &token)?;
+                self.emit_bytes(&[byte])
+                    .map_err(|message| self.error_at(&token, message))
+            }
+            ":pointer" => {
+                let target = self.next()?;
+                let address = self.here;
+                self.emit_bytes(&[0, 0])
+                    .map_err(|message| self.error_at(&target, message))?;
+                self.reference(address, FixupKind::Long, &target)
+            }
+            ":org" => {
+                let value = self.next()?;
+                let address = self.value(&value)? as i64;
+                if address < PROGRAM_START as i64 || address as usize > self.memory_size() {
+                    let message = format!("`:org` to {:#X} is outside the program", address);
+                    return Err(self.error_at(&value, message));
+                }
+                self.here = address as usize;
+                Ok(())
+            }
+            ":unpack" => {
+                let nibble = self.next()?;
+                let value = self.value(&nibble)? as i64;
+                if !(0..=0xF).contains(&value) {
+                    let message = format!("{} is not between 0 and 15", value);
+                    return Err(self.error_at(&nibble, message));
+                }
+                let target = self.next()?;
+                let address = self.here;
+                self.emit(Instruction::SetConst {
+                    register: 0,
+                    value: 0,
+                })?;
+                self.emit(Instruction::SetConst {
+                    register: 1,
+                    value: 0,
+                })?;
+                self.reference(address, FixupKind::Unpack(value as u8), &target)
+            }
+            ":call" => {
+                let target = self.next()?;
+                self.call(&target)
+            }
+            ":include" => self.include(&token),
+            ":breakpoint" => self.next().map(|_| ()),
+            ":monitor" => {
+                self.next()?;
+                self.next().map(|_| ())
+            }
+            ";" | "return" => self.emit(Instruction::Return),
+            "clear" => self.emit(Instruction::ClearScreen),
+            "exit" => self.emit_on(Platform::SuperChip, Instruction::Exit, &token),
+            "lores" => self.emit_on(Platform::SuperChip, Instruction::LowRes, &token),
+            "hires" => self.emit_on(Platform::SuperChip, Instruction::HighRes, &token),
+            "scroll-left" => self.emit_on(Platform::SuperChip, Instruction::ScrollLeft, &token),
+            "scroll-right" => self.emit_on(Platform::SuperChip, Instruction::ScrollRight, &token),
+            "scroll-down" => {
+                let rows = self.nibble()?;
+                self.emit_on(
+                    Platform::SuperChip,
+                    Instruction::ScrollDown { rows },
+                    &token,
+                )
+            }
+            "scroll-up" => {
+                let rows = self.nibble()?;
+                self.emit_on(Platform::XoChip, Instruction::ScrollUp { rows }, &token)
+            }
+            "audio" => self.emit_on(Platform::XoChip, Instruction::LoadAudio, &token),
+            "plane" => {
+                let planes = self.nibble()?;
+                if planes > 3 {
+                    let message = format!("plane {} is not between 0 and 3", planes);
+                    return Err(self.error_at(&self.last.clone(), message));
+                }
+                self.emit_on(
+                    Platform::XoChip,
+                    Instruction::SelectPlanes { planes },
+                    &token,
+                )
+            }
+            "bcd" => {
+                let register = self.next_register()?;
+                self.emit(Instruction::BinaryCodedDecimal { register })
+            }
+            "save" | "load" => self.save_or_load(&token),
+            "saveflags" | "loadflags" => {
+                let register = self.next_register()?;
+                let platform = if register > 7 {
+                    Platform::XoChip
+                } else {
+                    Platform::SuperChip
+                };
+                let instruction = if token.text == "saveflags" {
+                    Instruction::SaveFlags { register }
+                } else {
+                    Instruction::LoadFlags { register }
+                };
+                self.emit_on(platform, instruction, &token)
+            }
+            "sprite" => {
+                let x = self.next_register()?;
+                let y = self.next_register()?;
+                let height = self.nibble()?;
+                let platform = if height == 0 {
+                    Platform::SuperChip
+                } else {
+                    Platform::Chip8
+                };
+                self.emit_on(platform, Instruction::DrawSprite { x, y, height }, &token)
+            }
+            "jump" | "jump0" | "native" => {
+                let target = self.next()?;
+                let address = self.here;
+                let instruction = match token.text.as_str() {
+                    "jump" => Instruction::Jump { address: 0 },
+                    "jump0" => Instruction::JumpOffset { address: 0 },
+                    _ => Instruction::RCA1802 { address: 0 },
+                };
+                self.emit(instruction)?;
+                self.reference(address, FixupKind::Address, &target)
+            }
+            "i" => self.index_statement(),
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let register = self.next_register()?;
+                match token.text.as_str() {
+                    "delay" => self.emit(Instruction::SetTimer { register }),
+                    "buzzer" => self.emit(Instruction::SetSound { register }),
+                    _ => self.emit_on(Platform::XoChip, Instruction::SetPitch { register }, &token),
+                }
+            }
+            "if" => self.if_statement(),
+            "else" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) => {
+                    let address = self.here;
+                    self.emit(Instruction::Jump { address: 0 })?;
+                    self.patch_here(jump, &token)?;
+                    self.blocks.push(Block::Else {
+                        jump: address,
+                        token,
+                    });
+                    Ok(())
+                }
+                _ => Err(self.error_at(&token, "`else` without `if ... begin`".to_string())),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) | Some(Block::Else { jump, .. }) => {
+                    self.patch_here(jump, &token)
+                }
+                _ => Err(self.error_at(&token, "`end` without `if ... begin`".to_string())),
+            },
+            "loop" => {
+                self.blocks.push(Block::Loop {
+                    start: self.here,
+                    breaks: Vec::new(),
+                    token,
+                });
+                Ok(())
+            }
+            "while" => {
+                let condition = self.condition()?;
+                self.emit_condition(&condition, true)?;
+                let address = self.here;
+                self.emit(Instruction::Jump { address: 0 })?;
+                let breaks = self.blocks.iter_mut().rev().find_map(|block| match block {
+                    Block::Loop { breaks, .. } => Some(breaks),
+                    _ => None,
+                });
+                match breaks {
+                    Some(breaks) => breaks.push(address),
+                    None => return Err(self.error_at(&token, "`while` outside a loop".to_string())),
+                }
+                Ok(())
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, breaks, .. }) => {
+                    self.emit(Instruction::Jump {
+                        address: start as u16,
+                    })?;
+                    for address in breaks {
+                        self.patch_here(address, &token)?;
+                    }
+                    Ok(())
+                }
+                _ => Err(self.error_at(&token, "`again` without `loop`".to_string())),
+            },
+            text if self.macros.contains_key(text) => self.expand_macro(&token),
+            text if self.constants.contains_key(text) || is_number(text) => {
+                let byte = self.byte_value(&token)?;
+                self.emit_bytes(&[byte])
+                    .map_err(|message| self.error_at(&token, message))
+            }
+            text if text.starts_with(':') => {
+                Err(self.error_at(&token, format!("unknown directive `{}`", text)))
+            }
+            _ => self.call(&token),
+        }
+    }
+
+    fn register_statement(&mut self, register: u8, token: &Token) -> Result<()> {
+        let operator = self.next()?;
+        let source = self.next()?;
+        let source_register = self.register(&source.text);
+        use Instruction::*;
+        let instruction = match (operator.text.as_str(), source_register) {
+            (":=", Some(src)) => SetRegister {
+                dest: register,
+                src,
+            },
+            (":=", None) => match source.text.as_str() {
+                "random" => {
+                    let value = self.byte()?;
+                    Rand { register, value }
+                }
+                "key" => AwaitInput { register },
+                "delay" => GetTimer { register },
+                _ => SetConst {
+                    register,
+                    value: self.byte_value(&source)?,
+                },
+            },
+            ("+=", Some(b)) => Add { a: register, b },
+            ("+=", None) => AddConst {
+                register,
+                value: self.byte_value(&source)?,
+            },
+            ("-=", Some(b)) => Sub { a: register, b },
+            ("-=", None) => AddConst {
+                register,
+                value: self.byte_value(&source)?.wrapping_neg(),
+            },
+            ("=-", Some(b)) => NegSub { a: register, b },
+            ("|=", Some(b)) => Or { a: register, b },
+            ("&=", Some(b)) => And { a: register, b },
+            ("^=", Some(b)) => Xor { a: register, b },
+            (">>=", Some(b)) => ShiftRight { a: register, b },
+            ("<<=", Some(b)) => ShiftLeft { a: register, b },
+            _ => {
+                let message = format!(
+                    "expected an assignment to {}, got `{} {} {}`",
+                    token.text, token.text, operator.text, source.text
+                );
+                return Err(self.error_at(token, message));
+            }
+        };
+        self.emit(instruction)
+    }
+
+    fn index_statement(&mut self) -> Result<()> {
+        let operator = self.next()?;
+        match operator.text.as_str() {
+            "+=" => {
+                let register = self.next_register()?;
+                self.emit(Instruction::AddToI { register })
+            }
+            ":=" => {
+                let source = self.next()?;
+                match source.text.as_str() {
+                    "hex" => {
+                        let register = self.next_register()?;
+                        self.emit(Instruction::SetIToFontChar { register })
+                    }
+                    "bighex" => {
+                        let register = self.next_register()?;
+                        self.emit_on(
+                            Platform::SuperChip,
+                            Instruction::SetIToBigFontChar { register },
+                            &source,
+                        )
+                    }
+                    "long" => {
+                        let target = self.next()?;
+                        self.require(Platform::XoChip, &source)?;
+                        self.emit(Instruction::SetILong)?;
+                        let address = self.here;
+                        self.emit_bytes(&[0, 0])
+                            .map_err(|message| self.error_at(&target, message))?;
+                        self.reference(address, FixupKind::Long, &target)
+                    }
+                    _ => {
+                        let address = self.here;
+                        self.emit(Instruction::SetI { address: 0 })?;
+                        self.reference(address, FixupKind::Address, &source)
+                    }
+                }
+            }
+            _ => {
+                let message = format!("expected `:=` or `+=` after i, got `{}`", operator.text);
+                Err(self.error_at(&operator, message))
+            }
+        }
+    }
+
+    fn save_or_load(&mut self, token: &Token) -> Result<()> {
+        let a = self.next_register()?;
+        if !self.peek_is("-") {
+            return self.emit(if token.text == "save" {
+                Instruction::RegisterDump { register: a }
+            } else {
+                Instruction::RegisterLoad { register: a }
+            });
+        }
+        self.next()?;
+        let b = self.next_register()?;
+        let instruction = if token.text == "save" {
+            Instruction::SaveRange { a, b }
+        } else {
+            Instruction::LoadRange { a, b }
+        };
+        self.emit_on(Platform::XoChip, instruction, token)
+    }
+
+    fn if_statement(&mut self) -> Result<()> {
+        let condition = self.condition()?;
+        let token = self.next()?;
+        match token.text.as_str() {
+            "then" => self.emit_condition(&condition, false),
+            "begin" => {
+                self.emit_condition(&condition, true)?;
+                let jump = self.here;
+                self.emit(Instruction::Jump { address: 0 })?;
+                self.blocks.push(Block::If { jump, token });
+                Ok(())
+            }
+            _ => {
+                let message = format!("expected `then` or `begin`, got `{}`", token.text);
+                Err(self.error_at(&token, message))
+            }
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition> {
+        let register = self.next_register()?;
+        let operator = self.next()?;
+        let comparison = match operator.text.as_str() {
+            "key" => Comparison::Key,
+            "-key" => Comparison::NotKey,
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            ">" => Comparison::Greater,
+            "<=" => Comparison::LessOrEqual,
+            ">=" => Comparison::GreaterOrEqual,
+            _ => {
+                let message = format!("expected a comparison, got `{}`", operator.text);
+                return Err(self.error_at(&operator, message));
+            }
+        };
+        let operand = match comparison {
+            Comparison::Key | Comparison::NotKey => Operand::Register(register),
+            _ => {
+                let token = self.next()?;
+                match self.register(&token.text) {
+                    Some(register) => Operand::Register(register),
+                    None => Operand::Constant(self.byte_value(&token)?),
+                }
+            }
+        };
+        Ok(Condition {
+            register,
+            comparison,
+            operand,
+        })
+    }
+
+    /// Emits instructions after which the next one only runs if the
+    /// condition holds, or if it does not hold when `negate` is set.
+    fn emit_condition(&mut self, condition: &Condition, negate: bool) -> Result<()> {
+        use Comparison::*;
+        let comparison = if negate {
+            condition.comparison.negate()
+        } else {
+            condition.comparison
+        };
+        let x = condition.register;
+        let instruction = match (comparison, condition.operand) {
+            (Equal, Operand::Constant(value)) => {
+                Instruction::IfNotEqualConst { register: x, value }
+            }
+            (NotEqual, Operand::Constant(value)) => {
+                Instruction::IfEqualConst { register: x, value }
+            }
+            (Equal, Operand::Register(b)) => Instruction::IfNotEqualRegister { a: x, b },
+            (NotEqual, Operand::Register(b)) => Instruction::IfEqualRegister { a: x, b },
+            (Key, _) => Instruction::IfNotPressed { register: x },
+            (NotKey, _) => Instruction::IfPressed { register: x },
+            // VF is set to whether one side is at least the other, and the
+            // next instruction runs depending on whether it is set
+            (Less, operand) => {
+                self.emit_at_least(Operand::Register(x), operand)?;
+                Instruction::IfNotEqualConst {
+                    register: 0xF,
+                    value: 0,
+                }
+            }
+            (Greater, operand) => {
+                self.emit_at_least(operand, Operand::Register(x))?;
+                Instruction::IfNotEqualConst {
+                    register: 0xF,
+                    value: 0,
+                }
+            }
+            (LessOrEqual, operand) => {
+                self.emit_at_least(operand, Operand::Register(x))?;
+                Instruction::IfEqualConst {
+                    register: 0xF,
+                    value: 0,
+                }
+            }
+            (GreaterOrEqual, operand) => {
+                self.emit_at_least(Operand::Register(x), operand)?;
+                Instruction::IfEqualConst {
+                    register: 0xF,
+                    value: 0,
+                }
+            }
+        };
+        self.emit(instruction)
+    }
+
+    /// Sets VF to 1 if `a >= b` and to 0 otherwise.
+    fn emit_at_least(&mut self, a: Operand, b: Operand) -> Result<()> {
+        match (a, b) {
+            (a, Operand::Register(b)) => {
+                self.emit(match a {
+                    Operand::Register(a) => Instruction::SetRegister { dest: 0xF, src: a },
+                    Operand::Constant(value) => Instruction::SetConst {
+                        register: 0xF,
+                        value,
+                    },
+                })?;
+                self.emit(Instruction::Sub { a: 0xF, b })
+            }
+            (Operand::Register(a), Operand::Constant(value)) => {
+                self.emit(Instruction::SetConst {
+                    register: 0xF,
+                    value,
+                })?;
+                self.emit(Instruction::NegSub { a: 0xF, b: a })
+            }
+            (Operand::Constant(_), Operand::Constant(_)) => unreachable!(),
+        }
+    }
+
+    fn define_macro(&mut self) -> Result<()> {
+        let name = self.next()?;
+        self.check_name(&name)?;
+        let mut parameters = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "{" {
+                break;
+            }
+            parameters.push(token.text);
+        }
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                break;
+            }
+            body.push(token);
+        }
+        self.macros.insert(name.text, Macro { parameters, body });
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, token: &Token) -> Result<()> {
+        self.expansions += 1;
+        if self.expansions > MAX_MACRO_EXPANSIONS {
+            return Err(self.error_at(token, "too many macro expansions".to_string()));
+        }
+        let count = self.macros[&token.text].parameters.len();
+        let mut arguments = Vec::with_capacity(count);
+        for _ in 0..count {
+            arguments.push(self.next()?.text);
+        }
+        let definition = &self.macros[&token.text];
+        // Expanded tokens report the line of the invocation
+        let expanded: Vec<Token> = definition
+            .body
+            .iter()
+            .map(|body_token| {
+                let text = definition
+                    .parameters
+                    .iter()
+                    .position(|parameter| *parameter == body_token.text)
+                    .map_or_else(|| body_token.text.clone(), |i| arguments[i].clone());
+                Token {
+                    text,
+                    ..token.clone()
+                }
+            })
+            .collect();
+        for token in expanded.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+
+    fn include(&mut self, token: &Token) -> Result<()> {
+        let file = self.next()?;
+        let name = file
+            .text
+            .strip_prefix('"')
+            .and_then(|name| name.strip_suffix('"'))
+            .ok_or_else(|| self.error_at(&file, "expected a quoted file name".to_string()))?;
+        // Also stops a file from including itself forever
+        if self.files.len() > MAX_INCLUDED_FILES {
+            return Err(self.error_at(token, "too many included files".to_string()));
+        }
+        let path = self.files[token.file]
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(name);
+        let source = fs::read_to_string(&path).map_err(|e| {
+            self.error_at(&file, format!("failed to read {}: {}", path.display(), e))
+        })?;
+        self.files.push(path);
+        let tokens = tokenize(&source, self.files.len() - 1);
+        for token in tokens.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+
+    /// Evaluates a `:calc` expression between braces.
+    fn calc(&mut self) -> Result<f64> {
+        self.expect("{")?;
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "}" {
+                break;
+            }
+            tokens.push(token);
+        }
+        let mut position = 0;
+        let value = self.calc_expression(&tokens, &mut position)?;
+        match tokens.get(position) {
+            Some(token) => Err(self.error_at(token, format!("unexpected `{}`", token.text))),
+            None => Ok(value),
+        }
+    }
+
+    /// Octo evaluates expressions right to left without precedence:
+    /// `2 * 3 + 1` is 8.
+    fn calc_expression(&self, tokens: &[Token], position: &mut usize) -> Result<f64> {
+        let left = self.calc_term(tokens, position)?;
+        let operator = match tokens.get(*position) {
+            Some(token) if token.text != ")" => token,
+            _ => return Ok(left),
+        };
+        *position += 1;
+        let right = self.calc_expression(tokens, position)?;
+        let (a, b) = (left as i64, right as i64);
+        let value = match operator.text.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" | ">>" => {
+                let shift = u32::try_from(b).ok().and_then(|b| {
+                    if operator.text == "<<" {
+                        a.checked_shl(b)
+                    } else {
+                        a.checked_shr(b)
+                    }
+                });
+                match shift {
+                    Some(value) => value as f64,
+                    None => return Err(self.error_at(operator, "shift out of range".to_string())),
+                }
+            }
+            "<" => (left < right) as i64 as f64,
+            ">" => (left > right) as i64 as f64,
+            "<=" => (left <= right) as i64 as f64,
+            ">=" => (left >= right) as i64 as f64,
+            "==" => (left == right) as i64 as f64,
+            "!=" => (left != right) as i64 as f64,
+            _ => {
+                let message = format!("unknown operator `{}`", operator.text);
+                return Err(self.error_at(operator, message));
+            }
+        };
+        Ok(value)
+    }
+
+    fn calc_term(&self, tokens: &[Token], position: &mut usize) -> Result<f64> {
+        let token = match tokens.get(*position) {
+            Some(token) => token,
+            None => {
+                let token = tokens.last().unwrap_or(&self.last);
+                return Err(self.error_at(token, "expected a value".to_string()));
+            }
+        };
+        *position += 1;
+        let unary: Option<fn(f64) -> f64> = match token.text.as_str() {
+            "-" => Some(|x| -x),
+            "~" => Some(|x| !(x as i64) as f64),
+            "!" => Some(|x| (x == 0.0) as i64 as f64),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            "tan" => Some(f64::tan),
+            "exp" => Some(f64::exp),
+            "log" => Some(f64::ln),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "sign" => Some(f64::signum),
+            "ceil" => Some(f64::ceil),
+            "floor" => Some(f64::floor),
+            _ => None,
+        };
+        if let Some(function) = unary {
+            return self.calc_term(tokens, position).map(function);
+        }
+        match token.text.as_str() {
+            "(" => {
+                let value = self.calc_expression(tokens, position)?;
+                match tokens.get(*position) {
+                    Some(close) if close.text == ")" => {
+                        *position += 1;
+                        Ok(value)
+                    }
+                    _ => Err(self.error_at(token, "unclosed `(`".to_string())),
+                }
+            }
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(PI),
+            "E" => Ok(E),
+            _ => self.value(token),
+        }
+    }
+
+    /// A number, constant or already defined label.
+    fn value(&self, token: &Token) -> Result<f64> {
+        if let Some(value) = parse_number(&token.text) {
+            return Ok(value as f64);
+        }
+        if let Some(&value) = self.constants.get(&token.text) {
+            return Ok(value);
+        }
+        if let Some(&address) = self.labels.get(&token.text) {
+            return Ok(address as f64);
+        }
+        Err(self.error_at(token, format!("unknown value `{}`", token.text)))
+    }
+
+    fn byte_value(&self, token: &Token) -> Result<u8> {
+        self.fit_byte(self.value(token)?, token)
+    }
+
+    /// Checks that `value` fits in a byte, signed or unsigned.
+    fn fit_byte(&self, value: f64, token: &Token) -> Result<u8> {
+        let value = value as i64;
+        if !(-0x80..=0xFF).contains(&value) {
+            return Err(self.error_at(token, format!("{} does not fit in a byte", value)));
+        }
+        Ok(value as u8)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        let token = self.next()?;
+        self.byte_value(&token)
+    }
+
+    fn nibble(&mut self) -> Result<u8> {
+        let token = self.next()?;
+        let value = self.value(&token)? as i64;
+        if !(0..=0xF).contains(&value) {
+            return Err(self.error_at(&token, format!("{} is not between 0 and 15", value)));
+        }
+        Ok(value as u8)
+    }
+
+    fn register(&self, text: &str) -> Option<u8> {
+        if let Some(&register) = self.aliases.get(text) {
+            return Some(register);
+        }
+        let lower = text.to_ascii_lowercase();
+        lower
+            .strip_prefix('v')
+            .filter(|x| x.len() == 1)
+            .and_then(|x| u8::from_str_radix(x, 16).ok())
+    }
+
+    fn expect_register(&self, token: &Token) -> Result<u8> {
+        self.register(&token.text).ok_or_else(|| {
+            self.error_at(token, format!("expected a register, got `{}`", token.text))
+        })
+    }
+
+    fn next_register(&mut self) -> Result<u8> {
+        let token = self.next()?;
+        self.expect_register(&token)
+    }
+
+    fn call(&mut self, target: &Token) -> Result<()> {
+        let address = self.here;
+        self.emit(Instruction::Subroutine { address: 0 })?;
+        self.reference(address, FixupKind::Address, target)
+    }
+
+    /// Fills in the operand at `address` now if `target` is a known value,
+    /// or once it is defined if it is not.
+    fn reference(&mut self, address: usize, kind: FixupKind, target: &Token) -> Result<()> {
+        let known = parse_number(&target.text)
+            .or_else(|| self.constants.get(&target.text).map(|&value| value as i64))
+            .or_else(|| self.labels.get(&target.text).map(|&label| label as i64));
+        match known {
+            Some(value) if value < 0 => {
+                Err(self.error_at(target, format!("{} is not an address", value)))
+            }
+            Some(value) => self
+                .patch(address, kind, value as usize)
+                .map_err(|message| self.error_at(target, message)),
+            None => {
+                if is_number(&target.text) || target.text.starts_with(':') {
+                    let message = format!("expected a label, got `{}`", target.text);
+                    return Err(self.error_at(target, message));
+                }
+                self.fixups.push(Fixup {
+                    address,
+                    kind,
+                    token: target.clone(),
+                });
+                Ok(())
+            }
+        }
+    }
+
+    fn patch(
+        &mut self,
+        address: usize,
+        kind: FixupKind,
+        target: usize,
+    ) -> std::result::Result<(), String> {
+        let i = address - PROGRAM_START;
+        match kind {
+            FixupKind::Address => {
+                if target > 0xFFF {
+                    return Err(format!("address {:#X} does not fit in 12 bits", target));
+                }
+                self.rom[i] = self.rom[i] & 0xF0 | (target >> 8) as u8;
+                self.rom[i + 1] = target as u8;
+            }
+            FixupKind::Long => {
+                if target > 0xFFFF {
+                    return Err(format!("address {:#X} does not fit in 16 bits", target));
+                }
+                self.rom[i] = (target >> 8) as u8;
+                self.rom[i + 1] = target as u8;
+            }
+            FixupKind::Unpack(nibble) => {
+                if target > 0xFFF {
+                    return Err(format!("address {:#X} does not fit in 12 bits", target));
+                }
+                self.rom[i + 1] = nibble << 4 | (target >> 8) as u8;
+                self.rom[i + 3] = target as u8;
+            }
+        }
+        Ok(())
+    }
+
+    /// Points the jump at `address` to the current address.
+    fn patch_here(&mut self, address: usize, token: &Token) -> Result<()> {
+        self.patch(address, FixupKind::Address, self.here)
+            .map_err(|message| self.error_at(token, message))
+    }
+
+    fn emit(&mut self, instruction: Instruction) -> Result<()> {
+        let bytes = instruction.to_opcode().to_be_bytes();
+        self.emit_bytes(&bytes)
+            .map_err(|message| self.error_at(&self.last.clone(), message))
+    }
+
+    fn emit_on(
+        &mut self,
+        platform: Platform,
+        instruction: Instruction,
+        token: &Token,
+    ) -> Result<()> {
+        self.require(platform, token)?;
+        self.emit(instruction)
+    }
+
+    fn require(&self, platform: Platform, token: &Token) -> Result<()> {
+        if self.platform < platform {
+            let message = format!("`{}` needs {}", token.text, platform.name());
+            return Err(self.error_at(token, message));
+        }
+        Ok(())
+    }
+
+    fn emit_bytes(&mut self, bytes: &[u8]) -> std::result::Result<(), String> {
+        let end = self.here + bytes.len();
+        if end > self.memory_size() {
+            return Err(format!(
+                "the program does not fit in {} bytes of memory",
+                self.memory_size()
+            ));
+        }
+        let (start, end) = (self.here - PROGRAM_START, end - PROGRAM_START);
+        if self.rom.len() < end {
+            self.rom.resize(end, 0);
+        }
+        self.rom[start..end].copy_from_slice(bytes);
+        self.here += bytes.len();
+        Ok(())
+    }
+
+    fn memory_size(&self) -> usize {
+        if self.platform == Platform::XoChip {
+            EXTENDED_MEMORY_SIZE
+        } else {
+            MEMORY_SIZE
+        }
+    }
+
+    fn define_label(&mut self, name: &Token, address: usize) -> Result<()> {
+        self.check_name(name)?;
+        self.labels.insert(name.text.clone(), address);
+        Ok(())
+    }
+
+    fn define_constant(&mut self, name: &Token, value: f64) -> Result<()> {
+        self.check_name(name)?;
+        self.constants.insert(name.text.clone(), value);
+        Ok(())
+    }
+
+    /// Rejects names that are numbers, registers or already defined.
+    fn check_name(&self, name: &Token) -> Result<()> {
+        let text = &name.text;
+        if is_number(text) || self.register(text).is_some() || text.starts_with(':') {
+            return Err(self.error_at(name, format!("`{}` is not a valid name", text)));
+        }
+        if self.labels.contains_key(text)
+            || self.constants.contains_key(text)
+            || self.aliases.contains_key(text)
+            || self.macros.contains_key(text)
+        {
+            return Err(self.error_at(name, format!("`{}` is already defined", text)));
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.last = token.clone();
+                Ok(token)
+            }
+            None => Err(self.error_at(&self.last, "unexpected end of program".to_string())),
+        }
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens.front().is_some_and(|token| token.text == text)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<()> {
+        let token = self.next()?;
+        if token.text != text {
+            let message = format!("expected `{}`, got `{}`", text, token.text);
+            return Err(self.error_at(&token, message));
+        }
+        Ok(())
+    }
+
+    fn error_at(&self, token: &Token, message: String) -> AssemblerError {
+        AssemblerError {
+            path: self.files[token.file].clone(),
+            line: token.line,
+            message,
+        }
+    }
+}
+
+fn is_number(text: &str) -> bool {
+    parse_number(text).is_some()
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
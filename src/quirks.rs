@@ -0,0 +1,145 @@
+/// What FX55 and FX65 do to I after storing or loading registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexIncrement {
+    /// I is left unchanged.
+    Unchanged,
+    /// I is incremented by X.
+    X,
+    /// I is incremented by X + 1, pointing just past the last register.
+    XPlusOne,
+}
+
+/// Interpretations of instructions whose behaviour differs between CHIP-8
+/// implementations.
+///
+/// `Quirks::default()` is the behaviour this interpreter has always had,
+/// apart from where that was a bug. The named presets match the
+/// platforms most ROMs were written for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6 and 8XYE shift VX in place instead of storing VY shifted into VX.
+    pub shift_in_place: bool,
+    /// How FX55 and FX65 change I.
+    pub load_store_increment: IndexIncrement,
+    /// BNNN jumps to NNN + VX, where X is the high nibble of NNN, instead of
+    /// NNN + V0.
+    pub jump_uses_vx: bool,
+    /// 8XY1, 8XY2 and 8XY3 reset VF to 0.
+    pub logic_resets_vf: bool,
+    /// Sprites are clipped at the edges of the display instead of wrapping
+    /// around. The starting coordinate always wraps.
+    pub clip_sprites: bool,
+    /// FX1E sets VF to 1 when I moves past 0xFFF, and to 0 otherwise.
+    pub add_to_i_sets_vf: bool,
+    /// DXYN waits for the next timer tick, limiting drawing to one sprite
+    /// per frame as on the COSMAC VIP.
+    pub display_wait: bool,
+    /// Memory is 64 KB as on XO-CHIP instead of 4 KB.
+    pub extended_memory: bool,
+    /// Programs starting with 1260 use the COSMAC VIP two-page hires mode:
+    /// the jump goes to 0x2C0 instead, the display becomes 64x64 and 0230
+    /// clears it.
+    pub vip_hires: bool,
+    /// CXNN uses an approximation of the COSMAC VIP interpreter's random
+    /// number algorithm instead of a PCG generator. It has the same steps
+    /// and short cycles, but the numbers differ from a real VIP's.
+    pub vip_like_random: bool,
+    /// FX0A completes as soon as any key is held, instead of waiting for a
+    /// key to be released as on the COSMAC VIP. A key held down then
+    /// satisfies every FX0A it is held through.
+    pub key_wait_on_press: bool,
+    /// 00CN, 00FB to 00FF and DXY0 are the SUPER-CHIP scrolling, exit,
+    /// resolution and 16x16 sprite instructions. Otherwise the 00 opcodes
+    /// are machine code routines, which are ignored, and DXY0 draws nothing.
+    pub super_chip_instructions: bool,
+    /// 00DN, 5XY2, 5XY3, F000 NNNN, FN01, F002 and FX3A are the XO-CHIP
+    /// instructions, and skips step over all four bytes of F000 NNNN.
+    /// Otherwise 00DN is a machine code routine and the rest are invalid.
+    pub xo_chip_instructions: bool,
+}
+
+impl Quirks {
+    pub const COSMAC_VIP: Self = Self {
+        shift_in_place: false,
+        load_store_increment: IndexIncrement::XPlusOne,
+        jump_uses_vx: false,
+        logic_resets_vf: true,
+        clip_sprites: true,
+        add_to_i_sets_vf: false,
+        display_wait: true,
+        extended_memory: false,
+        vip_hires: true,
+        vip_like_random: false,
+        key_wait_on_press: false,
+        super_chip_instructions: false,
+        xo_chip_instructions: false,
+    };
+
+    pub const CHIP_48: Self = Self {
+        shift_in_place: true,
+        load_store_increment: IndexIncrement::X,
+        jump_uses_vx: true,
+        logic_resets_vf: false,
+        clip_sprites: true,
+        add_to_i_sets_vf: false,
+        display_wait: false,
+        extended_memory: false,
+        vip_hires: false,
+        vip_like_random: false,
+        key_wait_on_press: false,
+        super_chip_instructions: false,
+        xo_chip_instructions: false,
+    };
+
+    pub const SUPER_CHIP: Self = Self {
+        shift_in_place: true,
+        load_store_increment: IndexIncrement::Unchanged,
+        jump_uses_vx: true,
+        logic_resets_vf: false,
+        clip_sprites: true,
+        add_to_i_sets_vf: false,
+        display_wait: false,
+        extended_memory: false,
+        vip_hires: false,
+        vip_like_random: false,
+        key_wait_on_press: false,
+        super_chip_instructions: true,
+        xo_chip_instructions: false,
+    };
+
+    pub const XO_CHIP: Self = Self {
+        shift_in_place: false,
+        load_store_increment: IndexIncrement::XPlusOne,
+        jump_uses_vx: false,
+        logic_resets_vf: false,
+        clip_sprites: false,
+        add_to_i_sets_vf: false,
+        display_wait: false,
+        extended_memory: true,
+        vip_hires: false,
+        vip_like_random: false,
+        key_wait_on_press: false,
+        super_chip_instructions: true,
+        xo_chip_instructions: true,
+    };
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self {
+            shift_in_place: true,
+            load_store_increment: IndexIncrement::Unchanged,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            add_to_i_sets_vf: false,
+            display_wait: false,
+            extended_memory: false,
+            vip_hires: false,
+            vip_like_random: false,
+            key_wait_on_press: false,
+            super_chip_instructions: true,
+            xo_chip_instructions: true,
+        }
+    }
+}
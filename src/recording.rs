@@ -0,0 +1,57 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use chip8::movie::{Frame, Movie};
+use chip8::{Chip8, Input, Quirks};
+
+/// Records the run into a movie.
+///
+/// Key presses are held back until the next frame starts, so that the keys
+/// stay the same for the whole of every recorded frame.
+pub struct Recording {
+    path: PathBuf,
+    movie: Movie,
+    keys: Input,
+    cycles: u32,
+}
+
+impl Recording {
+    pub fn new(path: PathBuf, program: &[u8], quirks: Quirks, seed: u64) -> Self {
+        Self {
+            path,
+            movie: Movie::new(program, quirks, seed),
+            keys: Input::new(),
+            cycles: 0,
+        }
+    }
+
+    pub fn key_pressed(&mut self, key: u8) {
+        self.keys.key_pressed(key);
+    }
+
+    pub fn key_released(&mut self, key: u8) {
+        self.keys.key_released(key);
+    }
+
+    /// Counts one `Chip8::cycle` call.
+    pub fn cycle(&mut self) {
+        self.cycles += 1;
+    }
+
+    /// Ends the frame after a timer tick and starts the next one with the
+    /// keys pressed now.
+    pub fn end_frame(&mut self, chip8: &mut Chip8) {
+        self.movie.frames.push(Frame {
+            keys: chip8.input().bits(),
+            cycles: self.cycles,
+        });
+        self.cycles = 0;
+        chip8.set_input(self.keys.clone());
+    }
+
+    pub fn save(&self) -> Result<()> {
+        fs::write(&self.path, self.movie.to_bytes())
+            .with_context(|| format!("failed to write {}", self.path.display()))
+    }
+}
@@ -1,25 +1,83 @@
 use std::borrow::Cow;
+use std::str::FromStr;
 
+use glium::glutin::dpi::PhysicalSize;
+use glium::glutin::window::Fullscreen;
 use glium::index::{NoIndices, PrimitiveType};
-use glium::texture::{ClientFormat, RawImage2d, Texture2dDataSource};
+use glium::texture::{ClientFormat, RawImage2d};
 use glium::texture::{MipmapsOption, UncompressedUintFormat, UnsignedTexture2d};
 use glium::{implement_vertex, uniform, DrawParameters, Program, Rect, Surface, VertexBuffer};
 
-use crate::display as chip8_display;
+use chip8::Display as Chip8Display;
 
 const VERTEX_SHADER_SOURCE: &str = include_str!("shaders/default.vert");
 const FRAGMENT_SHADER_SOURCE: &str = include_str!("shaders/default.frag");
 
+/// The colours of the four pixel values, i.e. off, plane 1, plane 2 and both
+/// planes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Palette([[f32; 3]; 4]);
+
+impl Palette {
+    pub fn to_rgb8(self) -> [[u8; 3]; 4] {
+        self.0
+            .map(|color| color.map(|channel| (channel * 255.0).round() as u8))
+    }
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    /// Parses four comma-separated RGB hex colours, e.g. `000000,ffffff,aaaaaa,555555`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let colors = s
+            .split(',')
+            .map(parse_color)
+            .collect::<Result<Vec<_>, _>>()?;
+        if colors.len() != 4 {
+            return Err(format!("expected 4 colours, got {}", colors.len()));
+        }
+        Ok(Self([colors[0], colors[1], colors[2], colors[3]]))
+    }
+}
+
+fn parse_color(s: &str) -> Result<[f32; 3], String> {
+    let hex = s.trim().trim_start_matches('#');
+    let value = u32::from_str_radix(hex, 16)
+        .ok()
+        .filter(|_| hex.len() == 6)
+        .ok_or_else(|| format!("invalid colour `{}`, expected RRGGBB", s))?;
+    let channel = |shift: u32| ((value >> shift) & 0xFF) as f32 / 255.0;
+    Ok([channel(16), channel(8), channel(0)])
+}
+
 pub struct Renderer<'a> {
     display: glium::Display,
+    palette: Palette,
     shader_program: Program,
     vertex_buffer: VertexBuffer<Vertex>,
     draw_params: DrawParameters<'a>,
+    window_size: PhysicalSize<u32>,
+    scale: u32,
+    /// The dimensions of the CHIP-8 display when the window was last sized
+    /// for it.
+    dimensions: (u8, u8),
+    /// Whether the display changed resolution while in fullscreen, so that
+    /// the window needs resizing when leaving it.
+    stale_size: bool,
     initial_render: bool,
 }
 
 impl<'a> Renderer<'a> {
-    pub fn new(display: glium::Display) -> Self {
+    /// A renderer for a window sized by `window_size` for a CHIP-8 display
+    /// of `dimensions`, which it keeps resizing as the display changes
+    /// resolution.
+    pub fn new(
+        display: glium::Display,
+        palette: Palette,
+        scale: u32,
+        dimensions: (u8, u8),
+    ) -> Self {
         let vertices = [
             Vertex {
                 position: [-1.0, -1.0],
@@ -38,21 +96,37 @@ impl<'a> Renderer<'a> {
         let shader_program =
             Program::from_source(&display, VERTEX_SHADER_SOURCE, FRAGMENT_SHADER_SOURCE, None)
                 .unwrap();
+        let window_size = display.gl_window().window().inner_size();
         Self {
             display,
+            palette,
             vertex_buffer,
             shader_program,
             draw_params: DrawParameters::default(),
+            window_size,
+            scale,
+            dimensions,
+            stale_size: false,
             initial_render: true,
         }
     }
 
-    pub fn render(&mut self, chip8_display: &chip8_display::Display) {
+    pub fn render(&mut self, chip8_display: &Chip8Display) {
+        if chip8_display.dimensions() != self.dimensions {
+            self.dimensions = chip8_display.dimensions();
+            let gl_window = self.display.gl_window();
+            let window = gl_window.window();
+            match window.fullscreen() {
+                Some(_) => self.stale_size = true,
+                None => window.set_inner_size(window_size(self.dimensions, self.scale)),
+            }
+        }
         let mut frame = self.display.draw();
         frame.clear_color(0.0, 0.0, 0.0, 1.0);
+        self.draw_params.viewport = Some(self.viewport(chip8_display.dimensions()));
         let texture = UnsignedTexture2d::with_format(
             &self.display,
-            chip8_display.clone(),
+            raw_image(chip8_display),
             UncompressedUintFormat::U8,
             MipmapsOption::NoMipmap,
         )
@@ -62,7 +136,13 @@ impl<'a> Renderer<'a> {
                 &self.vertex_buffer,
                 NoIndices(PrimitiveType::TriangleStrip),
                 &self.shader_program,
-                &uniform! { texture: &texture },
+                &uniform! {
+                    textureSampler: &texture,
+                    color0: self.palette.0[0],
+                    color1: self.palette.0[1],
+                    color2: self.palette.0[2],
+                    color3: self.palette.0[3],
+                },
                 &self.draw_params,
             )
             .unwrap();
@@ -73,11 +153,58 @@ impl<'a> Renderer<'a> {
         }
     }
 
-    pub fn set_viewport(&mut self, viewport: Rect) {
-        self.draw_params.viewport = Some(viewport);
+    pub fn set_window_size(&mut self, window_size: PhysicalSize<u32>) {
+        self.window_size = window_size;
+    }
+
+    /// Switches between a window and borderless fullscreen on the monitor
+    /// the window is on.
+    pub fn toggle_fullscreen(&mut self) {
+        let gl_window = self.display.gl_window();
+        let window = gl_window.window();
+        match window.fullscreen() {
+            Some(_) => {
+                window.set_fullscreen(None);
+                if self.stale_size {
+                    window.set_inner_size(window_size(self.dimensions, self.scale));
+                    self.stale_size = false;
+                }
+            }
+            None => window.set_fullscreen(Some(Fullscreen::Borderless(window.current_monitor()))),
+        }
+    }
+
+    /// The largest centred area of the window that shows every CHIP-8 pixel
+    /// as the same whole number of screen pixels, leaving black bars on the
+    /// sides or at the top and bottom. A window too small for that gets the
+    /// largest area with the display's aspect ratio instead.
+    fn viewport(&self, (display_width, display_height): (u8, u8)) -> Rect {
+        let PhysicalSize { width, height } = self.window_size;
+        let (display_width, display_height) = (display_width as u32, display_height as u32);
+        let scale = (width / display_width).min(height / display_height);
+        let aspect_ratio = display_width as f32 / display_height as f32;
+        let (viewport_width, viewport_height) = if scale > 0 {
+            (display_width * scale, display_height * scale)
+        } else if width as f32 / height as f32 > aspect_ratio {
+            ((height as f32 * aspect_ratio) as u32, height)
+        } else {
+            (width, (width as f32 / aspect_ratio) as u32)
+        };
+        Rect {
+            left: (width - viewport_width) / 2,
+            bottom: (height - viewport_height) / 2,
+            width: viewport_width,
+            height: viewport_height,
+        }
     }
 }
 
+/// The size of a window showing a CHIP-8 display of `dimensions` with each
+/// CHIP-8 pixel as `scale` screen pixels.
+pub fn window_size((width, height): (u8, u8), scale: u32) -> PhysicalSize<u32> {
+    PhysicalSize::new(width as u32 * scale, height as u32 * scale)
+}
+
 #[derive(Debug, Clone, Copy)]
 struct Vertex {
     position: [f32; 2],
@@ -85,21 +212,13 @@ struct Vertex {
 
 implement_vertex!(Vertex, position);
 
-impl<'a> Texture2dDataSource<'a> for chip8_display::Display {
-    type Data = u8;
-
-    fn into_raw(self) -> RawImage2d<'a, Self::Data> {
-        let (width, height) = self.dimensions();
-        let data: Vec<_> = self
-            .into_inner()
-            .into_iter()
-            .map(|v| if v { 255 } else { 0 })
-            .collect();
-        RawImage2d {
-            width: width as u32,
-            height: height as u32,
-            format: ClientFormat::U8,
-            data: Cow::from(data),
-        }
+fn raw_image(chip8_display: &Chip8Display) -> RawImage2d<'static, u8> {
+    let (width, height) = chip8_display.dimensions();
+    let data = chip8_display.clone().into_inner();
+    RawImage2d {
+        width: width as u32,
+        height: height as u32,
+        format: ClientFormat::U8,
+        data: Cow::from(data),
     }
 }
@@ -0,0 +1,67 @@
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use chip8::debugger::Command;
+
+const PROMPT: &str = "(chip8) ";
+
+/// Reads debugger commands from stdin on a separate thread, so the window
+/// keeps running while waiting for input.
+pub struct Repl {
+    commands: Receiver<Command>,
+}
+
+impl Repl {
+    pub fn spawn() -> Self {
+        let (sender, commands) = mpsc::channel();
+        thread::spawn(move || {
+            let mut previous = None;
+            for line in io::stdin().lock().lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                let command = if line.trim().is_empty() {
+                    match previous {
+                        Some(command) => command,
+                        None => {
+                            prompt();
+                            continue;
+                        }
+                    }
+                } else {
+                    match line.parse() {
+                        Ok(command) => command,
+                        Err(error) => {
+                            println!("{}", error);
+                            prompt();
+                            continue;
+                        }
+                    }
+                };
+                previous = Some(command);
+                if sender.send(command).is_err() {
+                    break;
+                }
+            }
+        });
+        Self { commands }
+    }
+
+    /// The next command typed, if any.
+    pub fn try_recv(&self) -> Option<Command> {
+        self.commands.try_recv().ok()
+    }
+
+    /// Shows output followed by a fresh prompt.
+    pub fn print(&self, output: &str) {
+        println!("{}", output);
+        prompt();
+    }
+}
+
+fn prompt() {
+    print!("{}", PROMPT);
+    io::stdout().flush().ok();
+}
@@ -0,0 +1,149 @@
+//! Stepping back through the last frames of emulation, as save states kept
+//! compactly in memory.
+
+use std::collections::VecDeque;
+
+use crate::chip8::Chip8;
+
+/// A history of the last frames of emulation that can be stepped back
+/// through.
+///
+/// Only the newest save state is kept whole. Each older frame is stored as
+/// the XOR of its state with the next one, which is almost all zeros from
+/// one frame to the next, with the zero runs collapsed.
+pub struct Rewind {
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Delta>,
+}
+
+impl Rewind {
+    /// A history of at most `capacity` frames.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    /// Forgets every recorded frame, so that rewinding cannot go back past
+    /// a state that was loaded.
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    /// Records the current state of the machine, dropping the oldest frame
+    /// if the history is full.
+    pub fn push(&mut self, chip8: &Chip8) {
+        let state = chip8.save_state();
+        if let Some(previous) = &self.latest {
+            if self.capacity == 0 {
+                return;
+            }
+            if self.deltas.len() == self.capacity {
+                self.deltas.pop_front();
+            }
+            self.deltas.push_back(Delta::new(previous, &state));
+        }
+        self.latest = Some(state);
+    }
+
+    /// Restores the frame before the last one recorded, which becomes the
+    /// newest frame. Returns false once the history runs out.
+    pub fn step_back(&mut self, chip8: &mut Chip8) -> bool {
+        let (delta, latest) = match (self.deltas.pop_back(), &mut self.latest) {
+            (Some(delta), Some(latest)) => (delta, latest),
+            _ => return false,
+        };
+        delta.apply(latest);
+        // The keys held down now, not the ones held back then, should still
+        // count as pressed when the game resumes.
+        let input = chip8.input().clone();
+        chip8
+            .load_state(latest)
+            .expect("rewind history holds an invalid state");
+        chip8.set_input(input);
+        true
+    }
+}
+
+/// How to get from one save state back to the one before it.
+#[derive(Debug, Clone)]
+pub struct Delta {
+    /// The length of the older state. States differ in length when the
+    /// display changes resolution.
+    len: usize,
+    /// The XOR of the two states, padded with zeros to the longer one, as
+    /// pairs of a zero run length and a literal run length, each followed by
+    /// the literal bytes.
+    runs: Vec<u8>,
+}
+
+impl Delta {
+    pub fn new(older: &[u8], newer: &[u8]) -> Self {
+        let len = older.len().max(newer.len());
+        let xor: Vec<u8> = (0..len)
+            .map(|i| older.get(i).unwrap_or(&0) ^ newer.get(i).unwrap_or(&0))
+            .collect();
+        let mut runs = Vec::new();
+        let mut i = 0;
+        while i < len {
+            let zeros = xor[i..].iter().take_while(|&&b| b == 0).count();
+            i += zeros;
+            let literals = xor[i..].iter().take_while(|&&b| b != 0).count();
+            write_varint(&mut runs, zeros);
+            write_varint(&mut runs, literals);
+            runs.extend_from_slice(&xor[i..i + literals]);
+            i += literals;
+        }
+        Self {
+            len: older.len(),
+            runs,
+        }
+    }
+
+    /// Turns the newer state into the older one.
+    pub fn apply(&self, state: &mut Vec<u8>) {
+        let mut runs = &self.runs[..];
+        let mut i = 0;
+        while !runs.is_empty() {
+            i += read_varint(&mut runs);
+            let literals = read_varint(&mut runs);
+            if state.len() < i + literals {
+                state.resize(i + literals, 0);
+            }
+            for (byte, &x) in state[i..i + literals].iter_mut().zip(&runs[..literals]) {
+                *byte ^= x;
+            }
+            runs = &runs[literals..];
+            i += literals;
+        }
+        state.resize(self.len, 0);
+    }
+}
+
+/// Writes seven bits per byte, low bits first, with the top bit set on all
+/// but the last byte.
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[0];
+        *data = &data[1..];
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
@@ -0,0 +1,60 @@
+/// The random number generator behind CXNN.
+///
+/// It is seeded explicitly and its whole state fits in a save state, so a
+/// run can be repeated exactly from the same seed or snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Rng {
+    /// PCG-XSH-RR state.
+    pcg: u64,
+    /// The 16-bit seed register of the VIP-like algorithm.
+    vip: u16,
+}
+
+const PCG_MULTIPLIER: u64 = 6_364_136_223_846_793_005;
+const PCG_INCREMENT: u64 = 1_442_695_040_888_963_407;
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Self::from_state(0, seed as u16);
+        rng.next_pcg();
+        rng.pcg = rng.pcg.wrapping_add(seed);
+        rng.next_pcg();
+        rng
+    }
+
+    pub fn from_state(pcg: u64, vip: u16) -> Self {
+        Self { pcg, vip }
+    }
+
+    pub fn state(&self) -> (u64, u16) {
+        (self.pcg, self.vip)
+    }
+
+    /// A byte from a 32-bit PCG generator.
+    pub fn next_byte(&mut self) -> u8 {
+        (self.next_pcg() >> 24) as u8
+    }
+
+    /// A byte from an approximation of the COSMAC VIP interpreter's
+    /// algorithm: step the seed, then add the byte of `page` that the low
+    /// byte of the seed points at to the high byte, which is the result.
+    ///
+    /// The VIP reads its own interpreter code there. That code is not in
+    /// this machine's memory, so `page` is the 256 bytes that take its
+    /// place; the numbers have the same low quality and short cycles, but
+    /// not the values of a real VIP.
+    pub fn next_vip_like_byte(&mut self, page: &[u8]) -> u8 {
+        self.vip = self.vip.wrapping_add(1);
+        let [low, high] = self.vip.to_le_bytes();
+        let high = high.wrapping_add(page[low as usize]);
+        self.vip = u16::from_le_bytes([low, high]);
+        high
+    }
+
+    fn next_pcg(&mut self) -> u32 {
+        let old = self.pcg;
+        self.pcg = old.wrapping_mul(PCG_MULTIPLIER).wrapping_add(PCG_INCREMENT);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+}
@@ -0,0 +1,46 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+/// Keeps the SUPER-CHIP RPL user flags in a file so they survive restarts.
+pub struct RplFlagsFile {
+    path: PathBuf,
+    saved: [u8; 16],
+}
+
+impl RplFlagsFile {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            saved: [0; 16],
+        }
+    }
+
+    /// Reads the saved flags. A missing file means all flags are zero.
+    pub fn load(&mut self) -> Result<[u8; 16]> {
+        let bytes = match fs::read(&self.path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read {}", self.path.display()))
+            }
+        };
+        let n = bytes.len().min(self.saved.len());
+        self.saved = [0; 16];
+        self.saved[..n].copy_from_slice(&bytes[..n]);
+        Ok(self.saved)
+    }
+
+    /// Writes the flags if they changed since they were last loaded or saved.
+    pub fn save(&mut self, flags: &[u8; 16]) -> Result<()> {
+        if *flags == self.saved {
+            return Ok(());
+        }
+        fs::write(&self.path, flags)
+            .with_context(|| format!("failed to write {}", self.path.display()))?;
+        self.saved = *flags;
+        Ok(())
+    }
+}
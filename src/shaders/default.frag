@@ -4,9 +4,14 @@ in vec2 texCoord;
 out vec4 FragColor;
 
 uniform usampler2D textureSampler;
+uniform vec3 color0;
+uniform vec3 color1;
+uniform vec3 color2;
+uniform vec3 color3;
 
 void main()
 {
-    float c = texture(textureSampler, texCoord).r;
-    FragColor = vec4(c, c, c, 1.0);
+    vec3 palette[4] = vec3[4](color0, color1, color2, color3);
+    uint c = texture(textureSampler, texCoord).r;
+    FragColor = vec4(palette[min(c, 3u)], 1.0);
 }
@@ -0,0 +1,53 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chip8::Chip8;
+
+pub const SLOT_COUNT: u8 = 10;
+
+/// Save state slots kept next to the ROM as `<rom>.state0` to `<rom>.state9`.
+pub struct SaveSlots {
+    rom: PathBuf,
+    slot: u8,
+}
+
+impl SaveSlots {
+    pub fn new(rom: &Path) -> Self {
+        Self {
+            rom: rom.to_owned(),
+            slot: 0,
+        }
+    }
+
+    pub fn slot(&self) -> u8 {
+        self.slot
+    }
+
+    pub fn next_slot(&mut self) {
+        self.slot = (self.slot + 1) % SLOT_COUNT;
+    }
+
+    pub fn previous_slot(&mut self) {
+        self.slot = (self.slot + SLOT_COUNT - 1) % SLOT_COUNT;
+    }
+
+    pub fn save(&self, chip8: &Chip8) -> Result<()> {
+        let path = self.path();
+        fs::write(&path, chip8.save_state())
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    pub fn load(&self, chip8: &mut Chip8) -> Result<()> {
+        let path = self.path();
+        let state =
+            fs::read(&path).with_context(|| format!("failed to read {}", path.display()))?;
+        chip8
+            .load_state(&state)
+            .with_context(|| format!("failed to load {}", path.display()))
+    }
+
+    fn path(&self) -> PathBuf {
+        self.rom.with_extension(format!("state{}", self.slot))
+    }
+}
@@ -0,0 +1,254 @@
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+
+use crate::error::Chip8Error;
+use crate::quirks::{IndexIncrement, Quirks};
+
+pub(crate) const MAGIC: &[u8; 4] = b"C8ST";
+/// Bumped whenever the layout changes. Older versions are not read.
+pub(crate) const VERSION: u16 = 5;
+
+/// Why a save state could not be loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SaveStateError {
+    NotASaveState,
+    UnsupportedVersion {
+        version: u16,
+    },
+    /// The state was saved while running a different ROM.
+    RomMismatch {
+        expected: u64,
+        found: u64,
+    },
+    Corrupt,
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::NotASaveState => write!(f, "not a save state"),
+            Self::UnsupportedVersion { version } => write!(
+                f,
+                "save state version {} is not supported, expected version {}",
+                version, VERSION
+            ),
+            Self::RomMismatch { expected, found } => write!(
+                f,
+                "save state is for ROM {:016x}, not the running ROM {:016x}",
+                found, expected
+            ),
+            Self::Corrupt => write!(f, "save state is corrupt"),
+        }
+    }
+}
+
+impl Error for SaveStateError {}
+
+/// A 64-bit FNV-1a hash, used to tell ROMs apart.
+pub fn rom_hash(program: &[u8]) -> u64 {
+    program.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Appends little-endian fields to a save state.
+pub(crate) struct StateWriter(Vec<u8>);
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.0
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    /// Writes a length before the bytes.
+    pub fn sized_bytes(&mut self, bytes: &[u8]) {
+        self.u64(bytes.len() as u64);
+        self.bytes(bytes);
+    }
+
+    pub fn quirks(&mut self, quirks: &Quirks) {
+        self.bool(quirks.shift_in_place);
+        self.u8(match quirks.load_store_increment {
+            IndexIncrement::Unchanged => 0,
+            IndexIncrement::X => 1,
+            IndexIncrement::XPlusOne => 2,
+        });
+        self.bool(quirks.jump_uses_vx);
+        self.bool(quirks.logic_resets_vf);
+        self.bool(quirks.clip_sprites);
+        self.bool(quirks.add_to_i_sets_vf);
+        self.bool(quirks.display_wait);
+        self.bool(quirks.extended_memory);
+        self.bool(quirks.vip_hires);
+        self.bool(quirks.vip_like_random);
+        self.bool(quirks.key_wait_on_press);
+        self.bool(quirks.super_chip_instructions);
+        self.bool(quirks.xo_chip_instructions);
+    }
+
+    pub fn error(&mut self, error: Option<&Chip8Error>) {
+        match error {
+            None => self.u8(0),
+            Some(&Chip8Error::ProgramTooLarge { size, max }) => {
+                self.u8(1);
+                self.u64(size as u64);
+                self.u64(max as u64);
+            }
+            Some(&Chip8Error::InvalidOpcode { address, opcode }) => {
+                self.u8(2);
+                self.u16(address);
+                self.u16(opcode);
+            }
+            Some(&Chip8Error::StackOverflow { address }) => {
+                self.u8(3);
+                self.u16(address);
+            }
+            Some(&Chip8Error::StackUnderflow { address }) => {
+                self.u8(4);
+                self.u16(address);
+            }
+            Some(&Chip8Error::MemoryOutOfBounds { address, location }) => {
+                self.u8(5);
+                self.u16(address);
+                self.u64(location as u64);
+            }
+        }
+    }
+}
+
+/// Reads back the fields written by `StateWriter`. Running out of data or
+/// finding an impossible value means the state is corrupt.
+pub(crate) struct StateReader<'a>(&'a [u8]);
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self(data)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        if len > self.0.len() {
+            return Err(SaveStateError::Corrupt);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    pub fn sized_bytes(&mut self) -> Result<&'a [u8], SaveStateError> {
+        let len = self.u64()?;
+        self.bytes(len.try_into().map_err(|_| SaveStateError::Corrupt)?)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], SaveStateError> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::Corrupt),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn usize(&mut self) -> Result<usize, SaveStateError> {
+        self.u64()?.try_into().map_err(|_| SaveStateError::Corrupt)
+    }
+
+    pub fn quirks(&mut self) -> Result<Quirks, SaveStateError> {
+        Ok(Quirks {
+            shift_in_place: self.bool()?,
+            load_store_increment: match self.u8()? {
+                0 => IndexIncrement::Unchanged,
+                1 => IndexIncrement::X,
+                2 => IndexIncrement::XPlusOne,
+                _ => return Err(SaveStateError::Corrupt),
+            },
+            jump_uses_vx: self.bool()?,
+            logic_resets_vf: self.bool()?,
+            clip_sprites: self.bool()?,
+            add_to_i_sets_vf: self.bool()?,
+            display_wait: self.bool()?,
+            extended_memory: self.bool()?,
+            vip_hires: self.bool()?,
+            vip_like_random: self.bool()?,
+            key_wait_on_press: self.bool()?,
+            super_chip_instructions: self.bool()?,
+            xo_chip_instructions: self.bool()?,
+        })
+    }
+
+    pub fn error(&mut self) -> Result<Option<Chip8Error>, SaveStateError> {
+        let error = match self.u8()? {
+            0 => return Ok(None),
+            1 => Chip8Error::ProgramTooLarge {
+                size: self.usize()?,
+                max: self.usize()?,
+            },
+            2 => Chip8Error::InvalidOpcode {
+                address: self.u16()?,
+                opcode: self.u16()?,
+            },
+            3 => Chip8Error::StackOverflow {
+                address: self.u16()?,
+            },
+            4 => Chip8Error::StackUnderflow {
+                address: self.u16()?,
+            },
+            5 => Chip8Error::MemoryOutOfBounds {
+                address: self.u16()?,
+                location: self.usize()?,
+            },
+            _ => return Err(SaveStateError::Corrupt),
+        };
+        Ok(Some(error))
+    }
+}
@@ -0,0 +1,166 @@
+//! Logging every executed instruction, for bugs that are hard to catch by
+//! stepping through them in the debugger.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use crate::cpu::{Instruction, CPU};
+use crate::display::Display;
+use crate::error::Chip8Error;
+use crate::input::Input;
+
+/// One executed instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// The number of instructions traced before this one.
+    pub cycle: u64,
+    pub address: u16,
+    /// `None` if the instruction pointer was outside memory.
+    pub opcode: Option<u16>,
+    /// `None` if there was no valid instruction to decode.
+    pub instruction: Option<Instruction>,
+    /// The V registers the instruction changed as (register, old, new).
+    pub changes: Vec<(u8, u8, u8)>,
+    /// The value of I after the instruction.
+    pub i: u16,
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:>10}  {:03X}  ", self.cycle, self.address)?;
+        match self.opcode {
+            Some(opcode) => write!(f, "{:04X}  ", opcode)?,
+            None => write!(f, "----  ")?,
+        }
+        match &self.instruction {
+            Some(instruction) => write!(f, "{:?}", instruction)?,
+            None => write!(f, "(invalid)")?,
+        }
+        write!(f, "  I {:03X}", self.i)?;
+        for &(register, old, new) in &self.changes {
+            write!(f, "  V{:X} {:02X}->{:02X}", register, old, new)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes an [`Entry`] line to a log for every instruction a [`Chip8`] runs
+/// while the tracer is set on it, and keeps the last few entries. When the
+/// machine halts with an error those are written after the log, so the
+/// instructions that led up to it can be seen even if they were filtered
+/// out.
+///
+/// Writing stops at the first I/O error, which `finish` returns.
+///
+/// [`Chip8`]: crate::Chip8
+pub struct Tracer {
+    log: Box<dyn Write + Send>,
+    range: Option<RangeInclusive<u16>>,
+    history: VecDeque<Entry>,
+    history_len: usize,
+    cycles: u64,
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    /// Traces into `log`, keeping the last `history_len` entries.
+    pub fn new(log: Box<dyn Write + Send>, history_len: usize) -> Self {
+        Self {
+            log,
+            range: None,
+            history: VecDeque::with_capacity(history_len),
+            history_len,
+            cycles: 0,
+            error: None,
+        }
+    }
+
+    /// Only logs instructions at addresses in `range`, or all of them if it
+    /// is `None`. The history is kept for every instruction regardless.
+    pub fn set_range(&mut self, range: Option<RangeInclusive<u16>>) {
+        self.range = range;
+    }
+
+    /// The last entries, oldest first.
+    pub fn history(&self) -> impl Iterator<Item = &Entry> {
+        self.history.iter()
+    }
+
+    /// Flushes the log and returns the first error writing it, if any.
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => self.log.flush(),
+        }
+    }
+
+    /// Runs one instruction on `cpu` and traces it.
+    pub(crate) fn cycle(
+        &mut self,
+        cpu: &mut CPU,
+        display: &mut Display,
+        input: &Input,
+    ) -> Result<(), Chip8Error> {
+        if cpu.is_halted() {
+            return cpu.cycle(display, input);
+        }
+        let address = cpu.instruction_pointer();
+        let opcode = cpu.current_opcode();
+        let registers = *cpu.registers();
+        let result = cpu.cycle(display, input);
+
+        let changes = (0..16)
+            .filter(|&x| cpu.registers()[x] != registers[x])
+            .map(|x| (x as u8, registers[x], cpu.registers()[x]))
+            .collect();
+        let entry = Entry {
+            cycle: self.cycles,
+            address,
+            opcode,
+            instruction: opcode.and_then(Instruction::from_opcode),
+            changes,
+            i: cpu.address_register(),
+        };
+        self.cycles += 1;
+        if self
+            .range
+            .as_ref()
+            .map_or(true, |range| range.contains(&address))
+        {
+            self.write(format_args!("{}\n", entry));
+        }
+        if self.history_len > 0 {
+            if self.history.len() == self.history_len {
+                self.history.pop_front();
+            }
+            self.history.push_back(entry);
+        }
+        if let Err(error) = &result {
+            self.dump(error);
+        }
+        result
+    }
+
+    fn dump(&mut self, error: &Chip8Error) {
+        let mut dump = format!(
+            "halted: {}\nlast {} instructions:\n",
+            error,
+            self.history.len()
+        );
+        for entry in &self.history {
+            dump += &format!("{}\n", entry);
+        }
+        self.write(format_args!("{}", dump));
+        if self.error.is_none() {
+            self.error = self.log.flush().err();
+        }
+    }
+
+    fn write(&mut self, args: fmt::Arguments) {
+        if self.error.is_none() {
+            self.error = self.log.write_fmt(args).err();
+        }
+    }
+}
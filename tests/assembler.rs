@@ -0,0 +1,125 @@
+//! Tests for the assembler, and for the disassembler listing it reads back.
+
+use std::fs;
+use std::path::Path;
+
+use chip8::assembler::{assemble, AssemblerError};
+use chip8::disassembler::{disassemble, Syntax};
+
+fn assemble_source(source: &str) -> Result<Vec<u8>, AssemblerError> {
+    assemble(source, Path::new("test.asm"))
+}
+
+fn root() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+#[test]
+fn labels_can_be_used_before_they_are_defined() {
+    let source = "
+        start:  CALL sub
+                JP start
+        sub:    LD V0, 0x05   ; comment
+                RET
+    ";
+    assert_eq!(
+        assemble_source(source).unwrap(),
+        [0x22, 0x04, 0x12, 0x00, 0x60, 0x05, 0x00, 0xEE]
+    );
+}
+
+#[test]
+fn constants_and_bytes() {
+    let source = "
+        :const SPEED 3
+        :const FAST SPEED + 2
+                LD V1, FAST - 1
+                LD I, data
+        data:   :byte 1, SPEED, 0xFF, -1
+                :byte 0b1010 0x0A
+    ";
+    assert_eq!(
+        assemble_source(source).unwrap(),
+        [0x61, 0x04, 0xA2, 0x04, 0x01, 0x03, 0xFF, 0xFF, 0x0A, 0x0A]
+    );
+}
+
+#[test]
+fn includes_are_relative_to_the_including_file() {
+    let dir = std::env::temp_dir().join(format!("chip8-assembler-{}", std::process::id()));
+    fs::create_dir_all(dir.join("lib")).unwrap();
+    fs::write(dir.join("lib/sub.asm"), "sub: RET\n:include \"data.asm\"\n").unwrap();
+    fs::write(dir.join("lib/data.asm"), "data: :byte 7\n").unwrap();
+    let main = dir.join("main.asm");
+    let program = assemble("CALL sub\n:include \"lib/sub.asm\"\nLD I, data\n", &main);
+    let missing = assemble(":include \"missing.asm\"\n", &main).unwrap_err();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(program.unwrap(), [0x22, 0x02, 0x00, 0xEE, 0x07, 0xA2, 0x04]);
+    assert_eq!((missing.path, missing.line), (main, 1));
+    assert!(missing.message.starts_with("failed to read"));
+}
+
+#[test]
+fn errors_report_their_line() {
+    let cases = [
+        ("CLS\n\nJP nowhere\n", 3, "unknown name `nowhere`"),
+        ("CLS\nLD V0, 0x100\n", 2, "256 does not fit in a byte"),
+        ("x:\nx:\n", 2, "`x` is already defined"),
+        ("CLS\nFOO V0\n", 2, "unknown instruction `FOO`"),
+        (
+            "LD V0, 9223372036854775807 + 1\n",
+            1,
+            "`9223372036854775807 + 1` is out of range",
+        ),
+        (
+            "LD V0, 0 - 9223372036854775807 - 2\n",
+            1,
+            "`0 - 9223372036854775807 - 2` is out of range",
+        ),
+    ];
+    for &(source, line, message) in cases.iter() {
+        let error = assemble_source(source).unwrap_err();
+        assert_eq!(
+            (error.line, error.message.as_str()),
+            (line, message),
+            "{:?}",
+            source
+        );
+    }
+}
+
+/// The classic listing without its address and raw byte columns.
+fn listing_source(listing: &str) -> String {
+    listing
+        .lines()
+        .map(|line| match line.split_once("  ") {
+            Some((_address, rest)) => &rest[11..],
+            None => line,
+        })
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+#[test]
+fn disassembled_roms_assemble_back() {
+    let mut roms = Vec::new();
+    let mut dirs = vec![root().join("roms")];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension() == Some("ch8".as_ref()) {
+                roms.push(path);
+            }
+        }
+    }
+    assert!(roms.len() > 100);
+    for rom in &roms {
+        let program = fs::read(rom).unwrap();
+        let source = listing_source(&disassemble(&program, Syntax::Classic));
+        let assembled = assemble(&source, rom).unwrap_or_else(|e| panic!("{}", e));
+        assert!(assembled == program, "{} differs", rom.display());
+    }
+}
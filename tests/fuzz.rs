@@ -0,0 +1,125 @@
+//! Property tests that feed arbitrary bytes to everything that decodes them.
+//! Whatever the input, the result must be a value or a typed error, never a
+//! panic. The `fuzz` directory has cargo-fuzz targets for longer runs.
+
+use chip8::disassembler::{self, Syntax};
+use chip8::movie::Movie;
+use chip8::{Chip8, IndexIncrement, Input, Instruction, Quirks};
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+fn quirks() -> impl Strategy<Value = Quirks> {
+    (any::<[bool; 12]>(), 0..3u8).prop_map(|(flags, increment)| Quirks {
+        shift_in_place: flags[0],
+        load_store_increment: match increment {
+            0 => IndexIncrement::Unchanged,
+            1 => IndexIncrement::X,
+            _ => IndexIncrement::XPlusOne,
+        },
+        jump_uses_vx: flags[1],
+        logic_resets_vf: flags[2],
+        clip_sprites: flags[3],
+        add_to_i_sets_vf: flags[4],
+        display_wait: flags[5],
+        extended_memory: flags[6],
+        vip_hires: flags[7],
+        vip_like_random: flags[8],
+        key_wait_on_press: flags[9],
+        super_chip_instructions: flags[10],
+        xo_chip_instructions: flags[11],
+    })
+}
+
+/// Runs `frames` frames with a different set of keys held in each.
+fn run(chip8: &mut Chip8, frames: usize, keys: &[u16]) {
+    for frame in 0..frames {
+        chip8.set_input(Input::from_bits(keys[frame % keys.len()]));
+        for _ in 0..32 {
+            if chip8.cycle().is_err() {
+                return;
+            }
+        }
+        chip8.tick();
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(512))]
+
+    #[test]
+    fn random_programs_only_fail_with_errors(
+        program in vec(any::<u8>(), 0..0x400),
+        quirks in quirks(),
+        keys in vec(any::<u16>(), 1..8),
+        seed in any::<u64>(),
+    ) {
+        let mut chip8 = Chip8::new(&program, quirks).unwrap();
+        chip8.set_seed(seed);
+        run(&mut chip8, 64, &keys);
+        if let Err(error) = chip8.cycle() {
+            prop_assert_eq!(Some(&error), chip8.error());
+        }
+    }
+
+    /// Programs that run straight through extended memory into random code
+    /// at its very top, where the instruction pointer wraps around.
+    #[test]
+    fn programs_at_the_top_of_extended_memory(
+        tail in vec(any::<u8>(), 1..32),
+        quirks in quirks(),
+        seed in any::<u64>(),
+    ) {
+        let quirks = Quirks { extended_memory: true, ..quirks };
+        // 6000 sets V0 and nothing else
+        let mut program = [0x60, 0x00].repeat((0x10000 - 0x200) / 2);
+        let start = program.len() - tail.len();
+        program[start..].copy_from_slice(&tail);
+        let mut chip8 = Chip8::new(&program, quirks).unwrap();
+        chip8.set_seed(seed);
+        run(&mut chip8, 0x8000 / 32 + 64, &[0]);
+    }
+
+    #[test]
+    fn every_decoded_opcode_encodes_back(opcode in any::<u16>()) {
+        if let Some(instruction) = Instruction::from_opcode(opcode) {
+            prop_assert_eq!(instruction.to_opcode(), opcode);
+        }
+    }
+
+    #[test]
+    fn disassembling_never_panics(program in vec(any::<u8>(), 0..0x200)) {
+        disassembler::disassemble(&program, Syntax::Octo);
+        disassembler::disassemble(&program, Syntax::Classic);
+    }
+
+    #[test]
+    fn save_states_round_trip(
+        program in vec(any::<u8>(), 0..0x200),
+        quirks in quirks(),
+        keys in vec(any::<u16>(), 1..8),
+    ) {
+        let mut chip8 = Chip8::new(&program, quirks).unwrap();
+        run(&mut chip8, 16, &keys);
+        let state = chip8.save_state();
+        let mut restored = Chip8::new(&program, Quirks::default()).unwrap();
+        restored.load_state(&state).unwrap();
+        prop_assert_eq!(restored.save_state(), state);
+    }
+
+    #[test]
+    fn loading_garbage_states_never_panics(
+        garbage in vec(any::<u8>(), 0..0x100),
+        cut in any::<prop::sample::Index>(),
+        flip in any::<(prop::sample::Index, u8)>(),
+    ) {
+        let mut chip8 = Chip8::new(&[0x12, 0x00], Quirks::default()).unwrap();
+        let _ = chip8.load_state(&garbage);
+        let mut state = chip8.save_state();
+        let (index, bits) = flip;
+        let index = index.index(state.len());
+        state[index] ^= bits;
+        let _ = chip8.load_state(&state);
+        let _ = chip8.load_state(&state[..cut.index(state.len())]);
+        let _ = Movie::from_bytes(&garbage);
+    }
+}
@@ -0,0 +1,171 @@
+//! Talks to the GDB stub over a loopback socket the way a GDB client would.
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread::{self, JoinHandle};
+
+use chip8::gdb::{self, Status};
+use chip8::{Chip8, Quirks};
+
+struct Client {
+    stream: TcpStream,
+    acks: bool,
+}
+
+impl Client {
+    /// Starts a machine running `program` behind a stub and connects to it.
+    fn start(program: &'static [u8]) -> (Self, JoinHandle<(Chip8, Status)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut chip8 = Chip8::new(program, Quirks::default()).unwrap();
+            chip8.set_seed(0);
+            let status = gdb::serve(stream, &mut chip8, 12).unwrap();
+            (chip8, status)
+        });
+        let stream = TcpStream::connect(address).unwrap();
+        (Self { stream, acks: true }, server)
+    }
+
+    fn send(&mut self, data: &str) {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(self.stream, "${}#{:02x}", data, checksum).unwrap();
+        if self.acks {
+            assert_eq!(self.read_byte(), b'+');
+        }
+    }
+
+    fn receive(&mut self) -> String {
+        assert_eq!(self.read_byte(), b'$');
+        let mut data = Vec::new();
+        loop {
+            match self.read_byte() {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let checksum = [self.read_byte(), self.read_byte()];
+        let checksum = u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16).unwrap();
+        assert_eq!(
+            checksum,
+            data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+        );
+        if self.acks {
+            self.stream.write_all(b"+").unwrap();
+        }
+        String::from_utf8(data).unwrap()
+    }
+
+    fn request(&mut self, data: &str) -> String {
+        self.send(data);
+        self.receive()
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    fn pc(&mut self) -> u16 {
+        let value = self.request("p11");
+        u16::from_str_radix(&value[2..], 16).unwrap() << 8
+            | u16::from_str_radix(&value[..2], 16).unwrap()
+    }
+}
+
+// 200: 6005  V0 := 5
+// 202: 7001  V0 += 1
+// 204: A208  I := 208
+// 206: 1202  jump 202
+// 208: FFFF  not an instruction
+const LOOP: &[u8] = &[0x60, 0x05, 0x70, 0x01, 0xA2, 0x08, 0x12, 0x02, 0xFF, 0xFF];
+
+#[test]
+fn reads_and_writes_registers_and_memory() {
+    let (mut client, server) = Client::start(LOOP);
+    assert!(client
+        .request("qSupported:swbreak+")
+        .contains("QStartNoAckMode+"));
+    assert_eq!(client.request("QStartNoAckMode"), "OK");
+    client.acks = false;
+    assert_eq!(client.request("?"), "S05");
+
+    let target = client.request("qXfer:features:read:target.xml:0,1000");
+    assert!(target.starts_with("l<?xml"));
+    assert!(target.contains("name=\"pc\" bitsize=\"16\""));
+
+    // V0-VF, I, PC, SP, DT and ST
+    let registers = client.request("g");
+    assert_eq!(
+        registers,
+        format!("{}{}{}{}", "00".repeat(16), "0000", "0002", "000000")
+    );
+    assert_eq!(client.request("s"), "S05");
+    assert_eq!(client.request("p0"), "05");
+    assert_eq!(client.pc(), 0x202);
+
+    assert_eq!(client.request("P10=3412"), "OK");
+    assert_eq!(client.request("p10"), "3412");
+    assert_eq!(client.request("P12=05"), "E01");
+    assert_eq!(client.request("P15=zz"), "E01");
+
+    assert_eq!(client.request("m200,4"), "60057001");
+    assert_eq!(client.request("M202,2:7003"), "OK");
+    assert_eq!(client.request("m202,2"), "7003");
+    assert_eq!(client.request("mffe,4"), "0000");
+    assert_eq!(client.request("m1000,1"), "E01");
+    assert_eq!(client.request("Mfff,2:0000"), "E01");
+    assert_eq!(client.request("s"), "S05");
+    assert_eq!(client.request("p0"), "08");
+    assert_eq!(client.request("s1000"), "E01");
+    assert_eq!(client.request("c10200"), "E01");
+    assert_eq!(client.pc(), 0x204);
+
+    assert_eq!(client.request("vMustReplyEmpty"), "");
+    client.send("k");
+    let (chip8, status) = server.join().unwrap();
+    assert_eq!(status, Status::Killed);
+    assert_eq!(chip8.cpu().registers()[0], 8);
+}
+
+#[test]
+fn continues_to_breakpoints_and_reports_halts() {
+    let (mut client, server) = Client::start(LOOP);
+    assert_eq!(client.request("Z0,206,2"), "OK");
+    client.send("c");
+    assert_eq!(client.receive(), "S05");
+    assert_eq!(client.pc(), 0x206);
+    assert_eq!(client.request("p0"), "06");
+
+    // Continuing from a breakpoint runs its instruction first
+    client.send("c");
+    assert_eq!(client.receive(), "S05");
+    assert_eq!(client.request("p0"), "07");
+
+    assert_eq!(client.request("z0,206,2"), "OK");
+    assert_eq!(client.request("Z2,300,1"), "");
+    client.send("c208");
+    assert_eq!(client.receive(), "S04");
+    client.send("c");
+    assert_eq!(client.receive(), "S04");
+
+    assert_eq!(client.request("D"), "OK");
+    let (chip8, status) = server.join().unwrap();
+    assert_eq!(status, Status::Detached);
+    assert!(chip8.error().is_some());
+}
+
+#[test]
+fn interrupts_a_running_machine() {
+    let (mut client, server) = Client::start(LOOP);
+    client.send("c");
+    client.stream.write_all(&[0x03]).unwrap();
+    assert_eq!(client.receive(), "S02");
+    let pc = client.pc();
+    assert!((0x202..=0x206).contains(&pc));
+    drop(client);
+    let (_, status) = server.join().unwrap();
+    assert_eq!(status, Status::Detached);
+}
@@ -0,0 +1,100 @@
+//! Golden tests: ROMs are run headlessly and what they draw is compared with
+//! the files in `tests/golden`. Run with `UPDATE_GOLDEN=1` to rewrite those
+//! files after an intended change in behaviour.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chip8::headless::{self, End};
+use chip8::{Chip8, Quirks};
+
+const FRAMES: u32 = 600;
+const CYCLES_PER_FRAME: u32 = 12;
+const SEED: u64 = 0;
+
+fn root() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn run(rom: &Path) -> (Chip8, End) {
+    let program = fs::read(rom).unwrap();
+    let mut chip8 = Chip8::new(&program, Quirks::default()).unwrap();
+    chip8.set_seed(SEED);
+    let (_, end) = headless::run(&mut chip8, FRAMES, CYCLES_PER_FRAME);
+    (chip8, end)
+}
+
+/// Compares `actual` with the golden file `name`, or writes it there when
+/// updating.
+fn check_golden(name: &str, actual: &str) {
+    let path = root().join("tests/golden").join(name);
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("failed to read {}: {}", path.display(), e));
+    assert!(
+        actual == expected,
+        "{} differs from the golden file\nexpected:\n{}\nactual:\n{}",
+        name,
+        expected,
+        actual
+    );
+}
+
+/// The golden screen is the ROM's report with every check passed.
+#[test]
+fn test_opcode() {
+    let (chip8, end) = run(&root().join("roms/test_opcode.ch8"));
+    assert_eq!(end, End::Loop { address: 0x3DC });
+    check_golden("test_opcode.txt", &headless::text(chip8.display()));
+}
+
+/// The golden screen is the ROM's "BON" success message.
+#[test]
+fn bc_test() {
+    let (chip8, end) = run(&root().join("roms/BC_test.ch8"));
+    assert_eq!(end, End::Loop { address: 0x30E });
+    check_golden("BC_test.txt", &headless::text(chip8.display()));
+}
+
+/// Every game runs for ten seconds without input. Only the hash of the final
+/// display and how the run ended are kept.
+#[test]
+fn games() {
+    let mut roms: Vec<PathBuf> = fs::read_dir(root().join("roms/games"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension() == Some("ch8".as_ref()))
+        .collect();
+    roms.sort();
+    let mut actual = String::new();
+    for rom in &roms {
+        let (chip8, end) = run(rom);
+        actual += &format!(
+            "{:016x}  {}  ({})\n",
+            headless::hash(chip8.display()),
+            rom.file_name().unwrap().to_string_lossy(),
+            end
+        );
+    }
+    check_golden("games.txt", &actual);
+}
+
+/// Above 0xFFF the opcode that matches the address is not a jump to itself:
+/// 1234 at 0x1234 jumps to 0x234, which leads back to it.
+#[test]
+fn only_jumps_to_themselves_are_loops() {
+    let mut program = [0x60, 0x00].repeat(0x1034 / 2);
+    program.extend_from_slice(&[0x12, 0x34]);
+    let mut chip8 = Chip8::new(&program, Quirks::XO_CHIP).unwrap();
+    assert_eq!(headless::run(&mut chip8, 2, 5000), (2, End::FrameLimit));
+
+    let mut chip8 = Chip8::new(&[0x60, 0x00, 0x12, 0x02], Quirks::XO_CHIP).unwrap();
+    assert_eq!(
+        headless::run(&mut chip8, 2, 5000),
+        (1, End::Loop { address: 0x202 })
+    );
+}
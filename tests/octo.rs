@@ -0,0 +1,168 @@
+//! Tests for the Octo compiler. Each compiles a small program and compares
+//! the ROM with the expected words. Every program starts with the jump to
+//! `main` that the compiler emits at 0x200.
+
+use std::path::Path;
+
+use chip8::assembler::AssemblerError;
+use chip8::octo::{self, Platform};
+
+fn compile_for(source: &str, platform: Platform) -> Result<Vec<u8>, AssemblerError> {
+    octo::compile(source, Path::new("test.8o"), platform)
+}
+
+fn compile(source: &str) -> Vec<u16> {
+    let rom = compile_for(source, Platform::XoChip).unwrap();
+    rom.chunks(2)
+        .map(|word| u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)]))
+        .collect()
+}
+
+fn compile_error(source: &str) -> AssemblerError {
+    compile_for(source, Platform::XoChip).unwrap_err()
+}
+
+#[test]
+fn loops() {
+    let source = "
+        : main
+          loop
+            v0 += 1
+            while v0 != 5
+          again
+    ";
+    // The while skips the jump out of the loop while the condition holds
+    assert_eq!(compile(source), [0x1202, 0x7001, 0x4005, 0x120A, 0x1202]);
+}
+
+#[test]
+fn if_else_blocks() {
+    let source = "
+        : main
+          if v1 == 3 begin
+            v2 := 1
+          else
+            v2 := 2
+          end
+          if v1 key then v2 := 3
+    ";
+    assert_eq!(
+        compile(source),
+        [0x1202, 0x3103, 0x120A, 0x6201, 0x120C, 0x6202, 0xE1A1, 0x6203]
+    );
+}
+
+#[test]
+fn comparisons_go_through_vf() {
+    // VF is set to whether one side is at least the other, and then tested
+    let cases: [(&str, &[u16]); 4] = [
+        ("v0 < v1", &[0x8F00, 0x8F15, 0x4F00]),
+        ("v0 > 7", &[0x6F07, 0x8F05, 0x4F00]),
+        ("v0 <= v1", &[0x8F10, 0x8F05, 0x3F00]),
+        ("v0 >= 7", &[0x6F07, 0x8F07, 0x3F00]),
+    ];
+    for (condition, expected) in cases.iter() {
+        let source = format!(": main if {} then v2 := 1", condition);
+        let mut words = vec![0x1202];
+        words.extend_from_slice(expected);
+        words.push(0x6201);
+        assert_eq!(compile(&source), words, "{}", condition);
+    }
+}
+
+#[test]
+fn macros_calc_and_aliases() {
+    let source = "
+        :macro bump register amount { register += amount }
+        :calc eight { 2 * 3 + 1 }
+        :alias score v5
+        : main
+          bump v3 2
+          score := eight
+    ";
+    // :calc works right to left: 2 * (3 + 1)
+    assert_eq!(compile(source), [0x1202, 0x7302, 0x6508]);
+}
+
+#[test]
+fn unpack_next_and_org() {
+    let source = "
+        : main
+          :unpack 0xA data
+          :next target v0 := 5
+          i := target
+          i := far
+        : data
+          0x12 0x34
+        :org 0x300
+        : far
+          :byte -1
+    ";
+    let rom = compile_for(source, Platform::Chip8).unwrap();
+    assert_eq!(
+        rom[..16],
+        [
+            0x12, 0x02, 0x60, 0xA2, 0x61, 0x0C, 0x60, 0x05, 0xA2, 0x07, 0xA3, 0x00, 0x12, 0x34,
+            0x00, 0x00
+        ]
+    );
+    assert_eq!(rom.len(), 0x101);
+    assert_eq!(rom[0x100], 0xFF);
+}
+
+#[test]
+fn unknown_labels_report_their_line() {
+    let error = compile_error("\n: main\n  v0 := 1\n  jump nowhere\n");
+    assert_eq!(error.line, 4);
+    assert_eq!(error.message, "unknown label `nowhere`");
+    assert_eq!(error.to_string(), "test.8o:4: unknown label `nowhere`");
+}
+
+#[test]
+fn values_must_fit_in_a_byte() {
+    let error = compile_error(": main\n:byte 300");
+    assert_eq!(
+        (error.line, error.message.as_str()),
+        (2, "300 does not fit in a byte")
+    );
+    let error = compile_error(": main\n\n256");
+    assert_eq!(
+        (error.line, error.message.as_str()),
+        (3, "256 does not fit in a byte")
+    );
+    assert_eq!(compile(": main :byte { 0x7F + 1 } -128"), [0x1202, 0x8080]);
+}
+
+#[test]
+fn shifts_out_of_range_are_errors() {
+    for shift in &["1 << 64", "1 >> 64", "1 << -1"] {
+        let error = compile_error(&format!(": main\n:calc x {{ {} }}", shift));
+        assert_eq!(
+            (error.line, error.message.as_str()),
+            (2, "shift out of range")
+        );
+    }
+    assert_eq!(
+        compile(":calc x { 1 << 7 } : main v0 := x"),
+        [0x1202, 0x6080]
+    );
+}
+
+#[test]
+fn instructions_need_their_platform() {
+    let source = ": main\n  hires\n  plane 1\n";
+    let error = compile_for(source, Platform::Chip8).unwrap_err();
+    assert_eq!(
+        (error.line, error.message.as_str()),
+        (2, "`hires` needs SUPER-CHIP")
+    );
+    let error = compile_for(source, Platform::SuperChip).unwrap_err();
+    assert_eq!(
+        (error.line, error.message.as_str()),
+        (3, "`plane` needs XO-CHIP")
+    );
+    assert_eq!(compile(source), [0x1202, 0x00FF, 0xF101]);
+
+    let error = compile_for(": main\n  i := long 0x1234\n", Platform::SuperChip).unwrap_err();
+    assert_eq!(error.line, 2);
+}
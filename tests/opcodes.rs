@@ -0,0 +1,853 @@
+//! Conformance tests for every instruction.
+//!
+//! Most instructions are checked by a table of small programs: each runs
+//! until it falls off its end and the machine is then compared with a list of
+//! expectations. Timers, input, errors and the quirks that change control flow
+//! have their own tests below the table. The bundled test ROMs are covered by
+//! the golden tests.
+
+use chip8::{Chip8, Chip8Error, IndexIncrement, Quirks};
+
+const START: u16 = 0x200;
+
+/// Something to check about the machine after a program ran.
+#[derive(Debug, Clone, Copy)]
+enum Expect {
+    V(usize, u8),
+    I(u16),
+    Pc(u16),
+    Stack(&'static [u16]),
+    Memory(u16, &'static [u8]),
+    /// The value of the pixel at x, y.
+    Pixel(u8, u8, u8),
+    Dimensions(u8, u8),
+    Planes(u8),
+    DelayTimer(u8),
+    SoundTimer(u8),
+    AudioPattern(Option<u8>),
+    SampleRate(f32),
+    Exited,
+}
+
+use Expect::*;
+
+struct Case {
+    name: &'static str,
+    quirks: Quirks,
+    program: Vec<u16>,
+    expect: Vec<Expect>,
+}
+
+fn case(name: &'static str, program: &[u16], expect: &[Expect]) -> Case {
+    with_quirks(name, Quirks::default(), program, expect)
+}
+
+fn with_quirks(name: &'static str, quirks: Quirks, program: &[u16], expect: &[Expect]) -> Case {
+    Case {
+        name,
+        quirks,
+        program: program.to_vec(),
+        expect: expect.to_vec(),
+    }
+}
+
+fn machine(program: &[u16], quirks: Quirks) -> Chip8 {
+    let bytes: Vec<u8> = program.iter().flat_map(|word| word.to_be_bytes()).collect();
+    let mut chip8 = Chip8::new(&bytes, quirks).unwrap();
+    chip8.set_seed(0);
+    chip8
+}
+
+/// Runs until the instruction pointer reaches the end of the program or the
+/// machine halts.
+fn run(program: &[u16], quirks: Quirks) -> Chip8 {
+    let end = START + 2 * program.len() as u16;
+    let mut chip8 = machine(program, quirks);
+    for _ in 0..1000 {
+        if chip8.cpu().instruction_pointer() == end || chip8.is_halted() {
+            return chip8;
+        }
+        chip8.cycle().unwrap();
+    }
+    panic!("program did not finish");
+}
+
+fn check(chip8: &Chip8, expect: Expect) -> bool {
+    let cpu = chip8.cpu();
+    let display = chip8.display();
+    match expect {
+        V(register, value) => cpu.registers()[register] == value,
+        I(value) => cpu.address_register() == value,
+        Pc(value) => cpu.instruction_pointer() == value,
+        Stack(stack) => cpu.stack() == stack,
+        Memory(address, bytes) => {
+            let address = address as usize;
+            &cpu.memory()[address..address + bytes.len()] == bytes
+        }
+        Pixel(x, y, value) => display.get_pixel(x, y) == value,
+        Dimensions(width, height) => display.dimensions() == (width, height),
+        Planes(planes) => display.selected_planes() == planes,
+        DelayTimer(value) => cpu.delay_timer() == value,
+        SoundTimer(value) => cpu.sound_timer() == value,
+        AudioPattern(first) => chip8.audio_pattern().map(|pattern| pattern[0]) == first,
+        SampleRate(rate) => (chip8.audio_sample_rate() - rate).abs() < 0.01,
+        Exited => chip8.has_exited(),
+    }
+}
+
+fn quirks(change: impl FnOnce(&mut Quirks)) -> Quirks {
+    let mut quirks = Quirks::default();
+    change(&mut quirks);
+    quirks
+}
+
+fn cases() -> Vec<Case> {
+    let no_shift_in_place = quirks(|q| q.shift_in_place = false);
+    let logic_resets_vf = quirks(|q| q.logic_resets_vf = true);
+    let increment_x = quirks(|q| q.load_store_increment = IndexIncrement::X);
+    let increment_x_plus_one = quirks(|q| q.load_store_increment = IndexIncrement::XPlusOne);
+    let jump_uses_vx = quirks(|q| q.jump_uses_vx = true);
+    let add_to_i_sets_vf = quirks(|q| q.add_to_i_sets_vf = true);
+    let clip_sprites = quirks(|q| q.clip_sprites = true);
+    let extended_memory = quirks(|q| q.extended_memory = true);
+    // The table never ticks the timers, which DXYN waits for on the VIP
+    let cosmac_vip = Quirks {
+        display_wait: false,
+        ..Quirks::COSMAC_VIP
+    };
+
+    vec![
+        // 0NNN, 00E0, 00EE, 1NNN, 2NNN
+        case("0NNN is ignored", &[0x0123, 0x6001], &[V(0, 1)]),
+        case(
+            "00E0 clears the display",
+            &[0x6000, 0xF029, 0xD005, 0x00E0],
+            &[Pixel(0, 0, 0), Pixel(3, 4, 0)],
+        ),
+        case("1NNN jumps", &[0x1204, 0x6001, 0x6102], &[V(0, 0), V(1, 2)]),
+        case(
+            "2NNN pushes the return address",
+            &[0x2204, 0x6001],
+            &[Stack(&[0x202]), V(0, 0)],
+        ),
+        case(
+            "00EE returns past the call",
+            &[0x2206, 0x6102, 0x120A, 0x6001, 0x00EE],
+            &[V(0, 1), V(1, 2), Stack(&[])],
+        ),
+        // Skips
+        case(
+            "3XNN skips when equal",
+            &[0x6005, 0x3005, 0x6101, 0x6202],
+            &[V(1, 0), V(2, 2)],
+        ),
+        case(
+            "3XNN does not skip when different",
+            &[0x6005, 0x3006, 0x6101],
+            &[V(1, 1)],
+        ),
+        case(
+            "4XNN skips when different",
+            &[0x6005, 0x4006, 0x6101, 0x6202],
+            &[V(1, 0), V(2, 2)],
+        ),
+        case(
+            "4XNN does not skip when equal",
+            &[0x6005, 0x4005, 0x6101],
+            &[V(1, 1)],
+        ),
+        case(
+            "5XY0 skips when equal",
+            &[0x6005, 0x6105, 0x5010, 0x6201],
+            &[V(2, 0)],
+        ),
+        case(
+            "5XY0 does not skip when different",
+            &[0x6005, 0x6106, 0x5010, 0x6201],
+            &[V(2, 1)],
+        ),
+        case(
+            "9XY0 skips when different",
+            &[0x6005, 0x6106, 0x9010, 0x6201],
+            &[V(2, 0)],
+        ),
+        case(
+            "9XY0 does not skip when equal",
+            &[0x6005, 0x6105, 0x9010, 0x6201],
+            &[V(2, 1)],
+        ),
+        case(
+            "skipping F000 NNNN skips all four bytes",
+            &[0x6005, 0x3005, 0xF000, 0x6001, 0x6101],
+            &[V(0, 5), V(1, 1), I(0)],
+        ),
+        // Registers and arithmetic
+        case("6XNN sets a register", &[0x6A42], &[V(0xA, 0x42)]),
+        case(
+            "7XNN wraps and leaves VF alone",
+            &[0x60FF, 0x6F07, 0x7002],
+            &[V(0, 1), V(0xF, 7)],
+        ),
+        case("8XY0 copies", &[0x6105, 0x8010], &[V(0, 5), V(1, 5)]),
+        case("8XY1 ors", &[0x600C, 0x610A, 0x8011], &[V(0, 0x0E)]),
+        case("8XY2 ands", &[0x600C, 0x610A, 0x8012], &[V(0, 0x08)]),
+        case("8XY3 xors", &[0x600C, 0x610A, 0x8013], &[V(0, 0x06)]),
+        case("8XY1 leaves VF alone", &[0x6F05, 0x8011], &[V(0xF, 5)]),
+        with_quirks(
+            "8XY1 resets VF with logic_resets_vf",
+            logic_resets_vf,
+            &[0x6F05, 0x8011],
+            &[V(0xF, 0)],
+        ),
+        with_quirks(
+            "8XY2 resets VF with logic_resets_vf",
+            logic_resets_vf,
+            &[0x6F05, 0x8012],
+            &[V(0xF, 0)],
+        ),
+        with_quirks(
+            "8XY3 resets VF with logic_resets_vf",
+            logic_resets_vf,
+            &[0x6F05, 0x8013],
+            &[V(0xF, 0)],
+        ),
+        case(
+            "8XY4 sets VF on carry",
+            &[0x60FF, 0x6102, 0x8014],
+            &[V(0, 1), V(0xF, 1)],
+        ),
+        case(
+            "8XY4 clears VF without carry",
+            &[0x6001, 0x6102, 0x6F05, 0x8014],
+            &[V(0, 3), V(0xF, 0)],
+        ),
+        case(
+            "8XY4 with X = F keeps the carry, not the sum",
+            &[0x6FFF, 0x6101, 0x8F14],
+            &[V(0xF, 1)],
+        ),
+        case(
+            "8XY4 with X = F and no carry",
+            &[0x6F01, 0x6101, 0x8F14],
+            &[V(0xF, 0)],
+        ),
+        case(
+            "8XY4 with Y = F uses VF before the flag",
+            &[0x6003, 0x6F04, 0x80F4],
+            &[V(0, 7), V(0xF, 0)],
+        ),
+        case(
+            "8XY5 sets VF without borrow",
+            &[0x6007, 0x6105, 0x8015],
+            &[V(0, 2), V(0xF, 1)],
+        ),
+        case(
+            "8XY5 clears VF on borrow",
+            &[0x6005, 0x6107, 0x8015],
+            &[V(0, 0xFE), V(0xF, 0)],
+        ),
+        case(
+            "8XY5 of equal values sets VF",
+            &[0x6005, 0x6105, 0x8015],
+            &[V(0, 0), V(0xF, 1)],
+        ),
+        case(
+            "8XY5 with X = F keeps the flag",
+            &[0x6F05, 0x6107, 0x8F15],
+            &[V(0xF, 0)],
+        ),
+        case(
+            "8XY7 sets VF without borrow",
+            &[0x6005, 0x6107, 0x8017],
+            &[V(0, 2), V(0xF, 1)],
+        ),
+        case(
+            "8XY7 clears VF on borrow",
+            &[0x6007, 0x6105, 0x8017],
+            &[V(0, 0xFE), V(0xF, 0)],
+        ),
+        case(
+            "8XY7 with X = F keeps the flag",
+            &[0x6F07, 0x6105, 0x8F17],
+            &[V(0xF, 0)],
+        ),
+        case(
+            "8XY6 shifts VX in place",
+            &[0x6005, 0x61F0, 0x8016],
+            &[V(0, 2), V(1, 0xF0), V(0xF, 1)],
+        ),
+        with_quirks(
+            "8XY6 shifts VY into VX without shift_in_place",
+            no_shift_in_place,
+            &[0x6005, 0x61F0, 0x8016],
+            &[V(0, 0x78), V(1, 0xF0), V(0xF, 0)],
+        ),
+        case(
+            "8XY6 with X = F keeps the flag",
+            &[0x6F02, 0x8FF6],
+            &[V(0xF, 0)],
+        ),
+        case(
+            "8XYE shifts VX in place",
+            &[0x6081, 0x6140, 0x801E],
+            &[V(0, 0x02), V(0xF, 1)],
+        ),
+        with_quirks(
+            "8XYE shifts VY into VX without shift_in_place",
+            no_shift_in_place,
+            &[0x6081, 0x6140, 0x801E],
+            &[V(0, 0x80), V(1, 0x40), V(0xF, 0)],
+        ),
+        case(
+            "8XYE with X = F keeps the flag",
+            &[0x6F81, 0x8FFE],
+            &[V(0xF, 1)],
+        ),
+        case("CX00 is always 0", &[0x60FF, 0xC000], &[V(0, 0)]),
+        // I and memory
+        case("ANNN sets I", &[0xA123], &[I(0x123)]),
+        case(
+            "FX1E adds to I and leaves VF alone",
+            &[0xAFFF, 0x6001, 0x6F05, 0xF01E],
+            &[I(0x1000), V(0xF, 5)],
+        ),
+        with_quirks(
+            "FX1E sets VF past 0xFFF with add_to_i_sets_vf",
+            add_to_i_sets_vf,
+            &[0xAFFF, 0x6001, 0xF01E],
+            &[I(0x1000), V(0xF, 1)],
+        ),
+        with_quirks(
+            "FX1E clears VF below 0x1000 with add_to_i_sets_vf",
+            add_to_i_sets_vf,
+            &[0xA100, 0x6001, 0x6F05, 0xF01E],
+            &[I(0x101), V(0xF, 0)],
+        ),
+        case(
+            "FX29 points I at a small font character",
+            &[0x600A, 0xF029],
+            &[I(50)],
+        ),
+        case("FX29 uses the low nibble", &[0x601A, 0xF029], &[I(50)]),
+        case(
+            "FX30 points I at a big font character",
+            &[0x6003, 0xF030],
+            &[I(0x50 + 30)],
+        ),
+        case(
+            "FX33 stores BCD",
+            &[0xA300, 0x60FE, 0xF033],
+            &[Memory(0x300, &[2, 5, 4]), I(0x300)],
+        ),
+        case(
+            "FX33 stores leading zeros",
+            &[0xA300, 0x6007, 0xF033],
+            &[Memory(0x300, &[0, 0, 7])],
+        ),
+        case(
+            "FX55 stores V0 to VX and leaves I alone",
+            &[0xA300, 0x6001, 0x6102, 0x6203, 0x6304, 0xF255],
+            &[Memory(0x300, &[1, 2, 3, 0]), I(0x300)],
+        ),
+        with_quirks(
+            "FX55 increments I by X",
+            increment_x,
+            &[0xA300, 0xF255],
+            &[I(0x302)],
+        ),
+        with_quirks(
+            "FX55 increments I by X + 1",
+            increment_x_plus_one,
+            &[0xA300, 0xF255],
+            &[I(0x303)],
+        ),
+        case(
+            "FX65 loads V0 to VX",
+            &[
+                0xA300, 0x6001, 0x6102, 0x6203, 0xF255, 0x6000, 0x6100, 0x6200, 0x6309, 0xF265,
+            ],
+            &[V(0, 1), V(1, 2), V(2, 3), V(3, 9), I(0x300)],
+        ),
+        with_quirks(
+            "FX65 increments I by X + 1",
+            increment_x_plus_one,
+            &[0xA300, 0xF265],
+            &[I(0x303)],
+        ),
+        // Jumps with an offset
+        case(
+            "BNNN adds V0",
+            &[0x6004, 0xB204, 0x6101, 0x6201, 0x6301],
+            &[V(1, 0), V(2, 0), V(3, 1)],
+        ),
+        case(
+            "BXNN uses V0 without jump_uses_vx",
+            &[0x6204, 0x6002, 0xB204, 0x6101, 0x6201, 0x6301],
+            &[V(1, 1)],
+        ),
+        with_quirks(
+            "BXNN adds VX with jump_uses_vx",
+            jump_uses_vx,
+            &[0x6204, 0x6002, 0xB204, 0x6101, 0x6201, 0x6301],
+            &[V(1, 0), V(2, 1), V(3, 1)],
+        ),
+        // Sprites
+        case(
+            "DXYN draws a sprite",
+            &[0x6000, 0xF029, 0xD005],
+            &[
+                Pixel(0, 0, 1),
+                Pixel(3, 0, 1),
+                Pixel(4, 0, 0),
+                Pixel(0, 1, 1),
+                Pixel(1, 1, 0),
+                Pixel(3, 4, 1),
+                V(0xF, 0),
+            ],
+        ),
+        case(
+            "DXYN erases with XOR and sets VF",
+            &[0x6000, 0xF029, 0xD005, 0xD005],
+            &[Pixel(0, 0, 0), Pixel(3, 4, 0), V(0xF, 1)],
+        ),
+        case(
+            "DXYN without overlap clears VF",
+            &[0x6000, 0xF029, 0xD005, 0x6104, 0x6F07, 0xD105],
+            &[Pixel(0, 0, 1), Pixel(4, 0, 1), V(0xF, 0)],
+        ),
+        case(
+            "DXYN wraps the starting coordinate",
+            &[0x6000, 0xF029, 0x6142, 0xD105],
+            &[Pixel(2, 0, 1), Pixel(5, 0, 1), Pixel(6, 0, 0)],
+        ),
+        case(
+            "DXYN wraps sprites around the edges",
+            &[0x6000, 0xF029, 0x613E, 0x621E, 0xD125],
+            &[
+                Pixel(62, 30, 1),
+                Pixel(1, 30, 1),
+                Pixel(62, 0, 1),
+                Pixel(1, 1, 1),
+                Pixel(0, 1, 0),
+            ],
+        ),
+        with_quirks(
+            "DXYN clips sprites with clip_sprites",
+            clip_sprites,
+            &[0x6000, 0xF029, 0x613E, 0x621E, 0xD125],
+            &[
+                Pixel(62, 30, 1),
+                Pixel(63, 31, 0),
+                Pixel(1, 30, 0),
+                Pixel(62, 0, 0),
+                Pixel(1, 1, 0),
+            ],
+        ),
+        case(
+            "DXY0 draws a 16x16 sprite in hires",
+            &[
+                0x00FF, 0xA20A, 0x6000, 0xD000, 0x122A, 0xFFFF, 0x8001, 0x8001, 0x8001, 0x8001,
+                0x8001, 0x8001, 0x8001, 0x8001, 0x8001, 0x8001, 0x8001, 0x8001, 0x8001, 0x8001,
+                0xFFFF,
+            ],
+            &[
+                Pixel(0, 0, 1),
+                Pixel(15, 0, 1),
+                Pixel(16, 0, 0),
+                Pixel(0, 15, 1),
+                Pixel(15, 15, 1),
+                Pixel(1, 1, 0),
+                Pixel(0, 16, 0),
+            ],
+        ),
+        // Timers
+        case(
+            "FX15 and FX07 set and read the delay timer",
+            &[0x6009, 0xF015, 0xF107],
+            &[DelayTimer(9), V(1, 9)],
+        ),
+        case(
+            "FX18 sets the sound timer",
+            &[0x6009, 0xF018],
+            &[SoundTimer(9)],
+        ),
+        // SUPER-CHIP
+        case("00FF switches to hires", &[0x00FF], &[Dimensions(128, 64)]),
+        case(
+            "00FE switches back to lores",
+            &[0x00FF, 0x00FE],
+            &[Dimensions(64, 32)],
+        ),
+        case(
+            "00CN scrolls down",
+            &[0x6000, 0xF029, 0xD005, 0x00C2],
+            &[
+                Pixel(0, 0, 0),
+                Pixel(0, 2, 1),
+                Pixel(0, 6, 1),
+                Pixel(0, 7, 0),
+            ],
+        ),
+        case(
+            "00DN scrolls up",
+            &[0x6000, 0x6105, 0xF029, 0xD015, 0x00D2],
+            &[
+                Pixel(0, 3, 1),
+                Pixel(1, 3, 1),
+                Pixel(1, 4, 0),
+                Pixel(0, 8, 0),
+            ],
+        ),
+        case(
+            "00FB scrolls right by 4",
+            &[0x6000, 0xF029, 0xD005, 0x00FB],
+            &[
+                Pixel(0, 0, 0),
+                Pixel(4, 0, 1),
+                Pixel(7, 0, 1),
+                Pixel(8, 0, 0),
+            ],
+        ),
+        case(
+            "00FC scrolls left by 4",
+            &[0x6000, 0x6108, 0xF029, 0xD105, 0x00FC],
+            &[Pixel(4, 0, 1), Pixel(7, 0, 1), Pixel(8, 0, 0)],
+        ),
+        case("00FD exits", &[0x00FD, 0x6001], &[Exited, V(0, 0)]),
+        with_quirks(
+            "DXY0 draws nothing on the COSMAC VIP",
+            cosmac_vip,
+            &[0xAFFF, 0x6F01, 0x6000, 0xD000],
+            &[V(15, 0), Pixel(0, 0, 0), Pixel(7, 0, 0), Pc(0x208)],
+        ),
+        with_quirks(
+            "00CN and 00FB to 00FF are ignored on the COSMAC VIP",
+            cosmac_vip,
+            &[
+                0x6000, 0xF029, 0xD005, 0x00C2, 0x00FB, 0x00FC, 0x00FF, 0x00FD, 0x6001,
+            ],
+            &[Dimensions(64, 32), Pixel(0, 0, 1), Pixel(0, 7, 0), V(0, 1)],
+        ),
+        with_quirks(
+            "00DN is ignored on the COSMAC VIP",
+            cosmac_vip,
+            &[0x6000, 0xF029, 0xD005, 0x00D2, 0x6001],
+            &[Pixel(0, 0, 1), Pixel(0, 4, 1), V(0, 1)],
+        ),
+        with_quirks(
+            "skips step over F000 as one word on the COSMAC VIP",
+            cosmac_vip,
+            &[0x3000, 0xF000, 0x6001],
+            &[V(0, 1)],
+        ),
+        case(
+            "FX75 and FX85 save and restore flags",
+            &[0x6001, 0x6102, 0xF175, 0x6000, 0x6100, 0xF185],
+            &[V(0, 1), V(1, 2)],
+        ),
+        // XO-CHIP
+        case(
+            "5XY2 saves a register range",
+            &[0xA300, 0x6101, 0x6202, 0x6303, 0x5132],
+            &[Memory(0x300, &[1, 2, 3]), I(0x300)],
+        ),
+        case(
+            "5XY2 saves a range in reverse",
+            &[0xA300, 0x6101, 0x6202, 0x6303, 0x5312],
+            &[Memory(0x300, &[3, 2, 1])],
+        ),
+        case(
+            "5XY3 loads a register range",
+            &[
+                0xA300, 0x6101, 0x6202, 0x6303, 0x5132, 0x6100, 0x6200, 0x6300, 0x5133,
+            ],
+            &[V(1, 1), V(2, 2), V(3, 3), V(0, 0), V(4, 0)],
+        ),
+        with_quirks(
+            "F000 NNNN loads a 16-bit address into I",
+            extended_memory,
+            &[0xF000, 0xFFF0],
+            &[I(0xFFF0), Pc(0x204)],
+        ),
+        case("FN01 selects planes", &[0xF201], &[Planes(2)]),
+        case(
+            "DXYN draws to the selected plane",
+            &[0xF201, 0x6000, 0xF029, 0xD005],
+            &[Pixel(0, 0, 2), Pixel(4, 0, 0)],
+        ),
+        case(
+            "DXYN draws one sprite per selected plane",
+            &[0xF301, 0x6000, 0xF029, 0xD005],
+            &[
+                Pixel(0, 0, 1),
+                Pixel(2, 0, 3),
+                Pixel(0, 1, 1),
+                Pixel(2, 1, 2),
+            ],
+        ),
+        case(
+            "00E0 only clears the selected planes",
+            &[0xF301, 0x6000, 0xF029, 0xD005, 0xF101, 0x00E0],
+            &[Pixel(0, 0, 0), Pixel(2, 0, 2)],
+        ),
+        case(
+            "F002 loads the audio pattern",
+            &[0xA000, 0xF002],
+            &[AudioPattern(Some(0xF0))],
+        ),
+        case(
+            "no audio pattern by default",
+            &[0x6000],
+            &[AudioPattern(None)],
+        ),
+        case(
+            "FX3A sets the pitch",
+            &[0x6070, 0xF03A],
+            &[SampleRate(8000.0)],
+        ),
+    ]
+}
+
+#[test]
+fn instruction_table() {
+    let mut failures = Vec::new();
+    for case in cases() {
+        let chip8 = run(&case.program, case.quirks);
+        for &expect in &case.expect {
+            if !check(&chip8, expect) {
+                failures.push(format!("{}: expected {:?}", case.name, expect));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn timers_count_down_on_ticks() {
+    let mut chip8 = run(&[0x6003, 0xF015, 0x6002, 0xF018], Quirks::default());
+    assert!(chip8.should_play_sound());
+    chip8.tick();
+    assert_eq!(chip8.cpu().delay_timer(), 2);
+    assert_eq!(chip8.cpu().sound_timer(), 1);
+    for _ in 0..3 {
+        chip8.tick();
+    }
+    assert_eq!(chip8.cpu().delay_timer(), 0);
+    assert_eq!(chip8.cpu().sound_timer(), 0);
+    assert!(!chip8.should_play_sound());
+}
+
+#[test]
+fn key_skips() {
+    // V0 = 5, then EX9E and EXA1 each guard setting a register
+    let program = [0x6005, 0xE09E, 0x6101, 0xE0A1, 0x6201];
+    let mut chip8 = machine(&program, Quirks::default());
+    chip8.key_pressed(5);
+    for _ in 0..4 {
+        chip8.cycle().unwrap();
+    }
+    assert_eq!(chip8.cpu().registers()[1], 0);
+    assert_eq!(chip8.cpu().registers()[2], 1);
+
+    let mut chip8 = machine(&program, Quirks::default());
+    chip8.key_pressed(6);
+    for _ in 0..4 {
+        chip8.cycle().unwrap();
+    }
+    assert_eq!(chip8.cpu().registers()[1], 1);
+    assert_eq!(chip8.cpu().registers()[2], 0);
+}
+
+#[test]
+fn await_input_waits_for_a_key_release() {
+    let mut chip8 = machine(&[0xF30A, 0xF40A, 0x6001], Quirks::default());
+    for _ in 0..10 {
+        chip8.cycle().unwrap();
+    }
+    assert_eq!(chip8.cpu().instruction_pointer(), START);
+    chip8.key_pressed(0xB);
+    chip8.cycle().unwrap();
+    assert_eq!(chip8.cpu().instruction_pointer(), START);
+    chip8.key_released(0xB);
+    chip8.cycle().unwrap();
+    assert_eq!(chip8.cpu().registers()[3], 0xB);
+    assert_eq!(chip8.cpu().instruction_pointer(), START + 2);
+
+    // A key held down through the next FX0A does not complete it, but a tap
+    // between two cycles does
+    chip8.key_pressed(0xB);
+    chip8.cycle().unwrap();
+    chip8.key_pressed(0x2);
+    chip8.key_released(0x2);
+    chip8.cycle().unwrap();
+    assert_eq!(chip8.cpu().registers()[4], 0x2);
+    assert_eq!(chip8.cpu().instruction_pointer(), START + 4);
+}
+
+#[test]
+fn key_wait_on_press_completes_while_a_key_is_held() {
+    let quirks = quirks(|q| q.key_wait_on_press = true);
+    let mut chip8 = machine(&[0xF30A, 0xF40A, 0x6001], quirks);
+    chip8.cycle().unwrap();
+    assert_eq!(chip8.cpu().instruction_pointer(), START);
+    chip8.key_pressed(0xB);
+    chip8.cycle().unwrap();
+    chip8.cycle().unwrap();
+    assert_eq!(chip8.cpu().registers()[3], 0xB);
+    assert_eq!(chip8.cpu().registers()[4], 0xB);
+    assert_eq!(chip8.cpu().instruction_pointer(), START + 4);
+}
+
+#[test]
+fn display_wait_draws_once_per_frame() {
+    let quirks = quirks(|q| q.display_wait = true);
+    let mut chip8 = machine(&[0x6000, 0xF029, 0xD005, 0xD005], quirks);
+    for _ in 0..10 {
+        chip8.cycle().unwrap();
+    }
+    assert_eq!(chip8.cpu().instruction_pointer(), START + 4);
+    chip8.tick();
+    chip8.cycle().unwrap();
+    chip8.cycle().unwrap();
+    assert_eq!(chip8.cpu().instruction_pointer(), START + 6);
+    assert_eq!(chip8.display().get_pixel(0, 0), 1);
+}
+
+#[test]
+fn vip_hires_programs_start_at_2c0() {
+    let mut chip8 = machine(&[0x1260], quirks(|q| q.vip_hires = true));
+    chip8.cycle().unwrap();
+    assert_eq!(chip8.cpu().instruction_pointer(), 0x2C0);
+    assert_eq!(chip8.display().dimensions(), (64, 64));
+
+    let mut chip8 = machine(&[0x1260], Quirks::default());
+    chip8.cycle().unwrap();
+    assert_eq!(chip8.cpu().instruction_pointer(), 0x260);
+    assert_eq!(chip8.display().dimensions(), (64, 32));
+}
+
+#[test]
+fn extended_memory_is_64k() {
+    assert_eq!(machine(&[], Quirks::default()).cpu().memory().len(), 0x1000);
+    let quirks = quirks(|q| q.extended_memory = true);
+    assert_eq!(machine(&[], quirks).cpu().memory().len(), 0x10000);
+}
+
+#[test]
+fn rand_is_repeatable_from_a_seed() {
+    let program = [0xC0FF, 0xC1FF, 0xC2FF, 0xC3FF];
+    for &vip_like_random in &[false, true] {
+        let quirks = quirks(|q| q.vip_like_random = vip_like_random);
+        let draws = |seed| {
+            let mut chip8 = machine(&program, quirks);
+            chip8.set_seed(seed);
+            for _ in 0..program.len() {
+                chip8.cycle().unwrap();
+            }
+            chip8.cpu().registers()[..4].to_vec()
+        };
+        assert_eq!(draws(1), draws(1));
+        assert_ne!(draws(1), draws(2));
+    }
+}
+
+#[test]
+fn rand_masks_with_nn() {
+    let program: Vec<u16> = (0..16).map(|x| 0xC00F | x << 8).collect();
+    let chip8 = run(&program, Quirks::default());
+    assert!(chip8.cpu().registers().iter().all(|&v| v <= 0xF));
+}
+
+fn run_to_error(program: &[u16], quirks: Quirks) -> Chip8Error {
+    let mut chip8 = machine(program, quirks);
+    for _ in 0..100 {
+        if let Err(error) = chip8.cycle() {
+            return error;
+        }
+    }
+    panic!("program did not fail");
+}
+
+#[test]
+fn errors() {
+    assert_eq!(
+        run_to_error(&[0x2200], Quirks::default()),
+        Chip8Error::StackOverflow { address: 0x200 }
+    );
+    assert_eq!(
+        run_to_error(&[0x00EE], Quirks::default()),
+        Chip8Error::StackUnderflow { address: 0x200 }
+    );
+    assert_eq!(
+        run_to_error(&[0x6000, 0x5001], Quirks::default()),
+        Chip8Error::InvalidOpcode {
+            address: 0x202,
+            opcode: 0x5001
+        }
+    );
+    for &opcode in &[0x5012, 0x5013, 0xF000, 0xF101, 0xF002, 0xF03A] {
+        assert_eq!(
+            run_to_error(&[opcode], Quirks::COSMAC_VIP),
+            Chip8Error::InvalidOpcode {
+                address: 0x200,
+                opcode
+            },
+            "{:04X}",
+            opcode
+        );
+    }
+    assert!(matches!(
+        run_to_error(&[0xAFFF, 0xF155], Quirks::default()),
+        Chip8Error::MemoryOutOfBounds { address: 0x202, .. }
+    ));
+    assert!(matches!(
+        run_to_error(&[0xAFFE, 0xD005], Quirks::default()),
+        Chip8Error::MemoryOutOfBounds { address: 0x202, .. }
+    ));
+}
+
+#[test]
+fn errors_halt_the_machine() {
+    let mut chip8 = machine(&[0x6001, 0x00EE], Quirks::default());
+    chip8.cycle().unwrap();
+    let error = chip8.cycle().unwrap_err();
+    assert!(chip8.is_halted());
+    assert_eq!(chip8.cycle().unwrap_err(), error);
+    assert_eq!(chip8.error(), Some(&error));
+    assert_eq!(chip8.cpu().instruction_pointer(), 0x202);
+    assert_eq!(chip8.cpu().registers()[0], 1);
+}
+
+#[test]
+fn failed_draws_leave_the_machine_as_it_was() {
+    // The draw waits for a tick, then reads past the end of memory
+    let quirks = quirks(|q| q.display_wait = true);
+    let mut chip8 = machine(&[0xAFFF, 0xD00F], quirks);
+    chip8.cycle().unwrap();
+    chip8.tick();
+    let before = chip8.save_state();
+    chip8.cycle().unwrap_err();
+    let after = chip8.save_state();
+    // The states only differ by the error, which replaces the byte saying
+    // there is none
+    let same = before
+        .iter()
+        .zip(&after)
+        .take_while(|(a, b)| a == b)
+        .count();
+    assert_eq!(before[same], 0);
+    let rest = before.len() - same - 1;
+    assert_eq!(before[same + 1..], after[after.len() - rest..]);
+}
+
+#[test]
+fn program_too_large() {
+    let program = vec![0; 0x1000 - 0x200 + 1];
+    assert_eq!(
+        Chip8::new(&program, Quirks::default()).err(),
+        Some(Chip8Error::ProgramTooLarge {
+            size: program.len(),
+            max: 0x1000 - 0x200
+        })
+    );
+}
@@ -0,0 +1,89 @@
+use chip8::rewind::{Delta, Rewind};
+use chip8::{Chip8, Quirks};
+
+/// Checks that the delta from `older` to `newer` turns `newer` back into
+/// `older`.
+fn round_trip(older: &[u8], newer: &[u8]) {
+    let mut state = newer.to_vec();
+    Delta::new(older, newer).apply(&mut state);
+    assert!(state == older);
+}
+
+#[test]
+fn deltas_restore_the_older_state() {
+    let older: Vec<u8> = (0..2000).map(|i| (i * 7 % 251) as u8).collect();
+    round_trip(&older, &older);
+    round_trip(&[], &[]);
+
+    let mut newer = older.clone();
+    newer[1234] ^= 0x40;
+    round_trip(&older, &newer);
+    newer[0] ^= 1;
+    newer[1999] ^= 1;
+    round_trip(&older, &newer);
+
+    // Zero and literal runs longer than one varint byte holds
+    let mut newer = older.clone();
+    for byte in &mut newer[300..700] {
+        *byte ^= 0xFF;
+    }
+    round_trip(&older, &newer);
+    let newer: Vec<u8> = older.iter().map(|byte| !byte).collect();
+    round_trip(&older, &newer);
+}
+
+#[test]
+fn deltas_restore_states_of_another_length() {
+    let older: Vec<u8> = (0..300).map(|i| i as u8).collect();
+    round_trip(&older, &older[..100]);
+    round_trip(&older[..100], &older);
+    round_trip(&[], &older);
+    round_trip(&older, &[]);
+}
+
+/// Counts up in V0 one frame at a time.
+fn machine() -> Chip8 {
+    Chip8::new(&[0x70, 0x01, 0x12, 0x00], Quirks::default()).unwrap()
+}
+
+fn frame(chip8: &mut Chip8) {
+    chip8.cycle().unwrap();
+    chip8.cycle().unwrap();
+    chip8.tick();
+}
+
+#[test]
+fn the_oldest_frames_are_dropped() {
+    let mut chip8 = machine();
+    let mut rewind = Rewind::new(3);
+    for _ in 0..6 {
+        frame(&mut chip8);
+        rewind.push(&chip8);
+    }
+    assert_eq!(chip8.cpu().registers()[0], 6);
+    for v0 in (3..6).rev() {
+        assert!(rewind.step_back(&mut chip8));
+        assert_eq!(chip8.cpu().registers()[0], v0);
+    }
+    assert!(!rewind.step_back(&mut chip8));
+    assert_eq!(chip8.cpu().registers()[0], 3);
+}
+
+#[test]
+fn cleared_history_cannot_be_rewound() {
+    let mut chip8 = machine();
+    let mut rewind = Rewind::new(10);
+    for _ in 0..3 {
+        frame(&mut chip8);
+        rewind.push(&chip8);
+    }
+    rewind.clear();
+    assert!(!rewind.step_back(&mut chip8));
+    frame(&mut chip8);
+    rewind.push(&chip8);
+    frame(&mut chip8);
+    rewind.push(&chip8);
+    assert!(rewind.step_back(&mut chip8));
+    assert_eq!(chip8.cpu().registers()[0], 4);
+    assert!(!rewind.step_back(&mut chip8));
+}
@@ -0,0 +1,50 @@
+//! Save state tests beyond the round trips of the property tests.
+
+use chip8::{Chip8, Quirks, SaveStateError};
+
+/// Switches to 128x64 and draws the font's 0 in the corner.
+const PROGRAM: [u8; 6] = [0x00, 0xFF, 0xA0, 0x00, 0xD0, 0x05];
+
+fn machine() -> Chip8 {
+    let mut chip8 = Chip8::new(&PROGRAM, Quirks::default()).unwrap();
+    chip8.set_seed(0);
+    chip8
+}
+
+#[test]
+fn states_round_trip() {
+    let mut chip8 = machine();
+    for _ in 0..3 {
+        chip8.cycle().unwrap();
+    }
+    chip8.key_pressed(7);
+    let state = chip8.save_state();
+
+    let mut restored = machine();
+    restored.load_state(&state).unwrap();
+    assert_eq!(restored.display().dimensions(), (128, 64));
+    assert_eq!(restored.display().get_pixel(0, 0), 1);
+    assert_eq!(restored.cpu().instruction_pointer(), 0x206);
+    assert!(restored.input().is_key_pressed(7));
+    assert_eq!(restored.save_state(), state);
+}
+
+#[test]
+fn states_with_other_display_sizes_are_corrupt() {
+    let chip8 = machine();
+    let state = chip8.save_state();
+    // The display is the width, height, selected planes and 64x32 pixels,
+    // followed by the two bytes of held keys
+    let display = state.len() - 2 - 64 * 32 - 3;
+    assert_eq!(state[display..display + 2], [64, 32]);
+    for &(width, height) in &[(0, 32), (64, 0), (1, 1), (32, 64), (255, 255)] {
+        let mut corrupt = state[..display].to_vec();
+        corrupt.extend_from_slice(&[width, height, 1]);
+        corrupt.resize(corrupt.len() + width as usize * height as usize, 0);
+        corrupt.extend_from_slice(&state[state.len() - 2..]);
+
+        let mut chip8 = machine();
+        assert_eq!(chip8.load_state(&corrupt), Err(SaveStateError::Corrupt));
+        assert_eq!(chip8.display().dimensions(), (64, 32));
+    }
+}
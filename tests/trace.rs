@@ -0,0 +1,66 @@
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use chip8::trace::Tracer;
+use chip8::{Chip8, Quirks};
+
+/// A log the test can read back after handing it to the tracer.
+#[derive(Clone, Default)]
+struct Log(Arc<Mutex<Vec<u8>>>);
+
+impl Log {
+    fn lines(&self) -> Vec<String> {
+        let text = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+        text.lines().map(str::to_string).collect()
+    }
+}
+
+impl Write for Log {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// 6005 6107 8014 A300 00EE: the return has no subroutine to return from.
+const PROGRAM: [u8; 10] = [0x60, 0x05, 0x61, 0x07, 0x80, 0x14, 0xA3, 0x00, 0x00, 0xEE];
+
+fn trace(history_len: usize, range: Option<std::ops::RangeInclusive<u16>>) -> (Chip8, Log) {
+    let log = Log::default();
+    let mut tracer = Tracer::new(Box::new(log.clone()), history_len);
+    tracer.set_range(range);
+    let mut chip8 = Chip8::new(&PROGRAM, Quirks::default()).unwrap();
+    chip8.set_tracer(Some(tracer));
+    while chip8.cycle().is_ok() {}
+    (chip8, log)
+}
+
+#[test]
+fn logs_every_instruction_and_dumps_the_history_on_error() {
+    let (mut chip8, log) = trace(2, None);
+    let history: Vec<_> = chip8.tracer().unwrap().history().cloned().collect();
+    assert_eq!(history.len(), 2);
+    assert_eq!((history[0].address, history[0].i), (0x206, 0x300));
+    assert_eq!(history[1].opcode, Some(0x00EE));
+    chip8.take_tracer().unwrap().finish().unwrap();
+
+    let lines = log.lines();
+    assert_eq!(lines.len(), 5 + 2 + 2);
+    assert!(lines[2].contains("204  8014  Add { a: 0, b: 1 }  I 000  V0 05->0C"));
+    assert_eq!(lines[5], "halted: return with an empty stack at 208");
+    assert_eq!(lines[6], "last 2 instructions:");
+    assert_eq!(lines[7..], lines[3..5]);
+}
+
+#[test]
+fn range_filters_the_log_but_not_the_dump() {
+    let (_, log) = trace(8, Some(0x202..=0x204));
+    let lines = log.lines();
+    assert_eq!(lines.len(), 2 + 2 + 5);
+    assert!(lines[0].contains("202  6107"));
+    assert!(lines[1].contains("204  8014"));
+    assert_eq!(lines[3], "last 5 instructions:");
+}